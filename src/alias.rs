@@ -0,0 +1,106 @@
+// alias.rs
+
+use std::collections::HashMap;
+use std::sync::{Mutex, MutexGuard, OnceLock};
+
+static ALIASES: OnceLock<Mutex<HashMap<String, String>>> = OnceLock::new();
+
+fn table() -> MutexGuard<'static, HashMap<String, String>> {
+    ALIASES.get_or_init(|| Mutex::new(HashMap::new())).lock().unwrap()
+}
+
+/// Alias names may not contain quoting, expansions or syntax characters.
+pub fn is_valid_name(name: &str) -> bool {
+    !name.is_empty() && !name.contains(|c: char| c.is_whitespace() || "'\"\\$`=/;&|()<>".contains(c))
+}
+
+pub fn define(name: &str, value: &str) {
+    table().insert(name.to_string(), value.to_string());
+}
+
+pub fn get(name: &str) -> Option<String> {
+    table().get(name).cloned()
+}
+
+/// Removes an alias. Returns false if there was none by that name.
+pub fn remove(name: &str) -> bool {
+    table().remove(name).is_some()
+}
+
+pub fn clear() {
+    table().clear();
+}
+
+/// All aliases sorted by name.
+pub fn all() -> Vec<(String, String)> {
+    let mut aliases: Vec<_> = table().iter().map(|(n, v)| (n.clone(), v.clone())).collect();
+    aliases.sort();
+    aliases
+}
+
+/// The `alias name='value'` line that redefines an alias.
+pub fn definition(name: &str, value: &str) -> String {
+    format!("alias {}='{}'", name, value.replace('\'', "'\\''"))
+}
+
+/// Expands an alias in the first word of a command's source text. Returns
+/// `None` if the first word is not an alias.
+pub fn expand(text: &str) -> Option<String> {
+    let mut seen = Vec::new();
+    let mut expanded = expand_word(text.trim_start(), &mut seen)?;
+    // The first word of the result has already been tried, so quote it to
+    // keep it from being expanded again when the text is run.
+    if get(first_word(&expanded).0).is_some() {
+        expanded.insert(0, '\\');
+    }
+    Some(expanded)
+}
+
+/// Expands the alias at the start of `text`. The first word of an alias's
+/// value is expanded in turn, unless it names an alias already being
+/// expanded; a value ending in a blank makes the next word a candidate too.
+fn expand_word(text: &str, seen: &mut Vec<String>) -> Option<String> {
+    let (word, rest) = first_word(text);
+    if seen.iter().any(|s| s == word) {
+        return None;
+    }
+    let value = get(word)?;
+    // The guard only covers the chain of values expanded from this word;
+    // the word after a value ending in a blank starts from the outer one.
+    let mut outer = seen.clone();
+    seen.push(word.to_string());
+    let mut out = expand_word(&value, seen).unwrap_or_else(|| value.clone());
+    let next = rest.trim_start();
+    if value.ends_with([' ', '\t']) && !next.is_empty() {
+        out.push_str(&rest[..rest.len() - next.len()]);
+        out.push_str(&expand_word(next, &mut outer).unwrap_or_else(|| next.to_string()));
+    } else {
+        out.push_str(rest);
+    }
+    Some(out)
+}
+
+fn first_word(text: &str) -> (&str, &str) {
+    text.split_at(text.find(char::is_whitespace).unwrap_or(text.len()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn value_ending_in_blank_expands_next_word() {
+        define("alias_test_a", "alias_test_b ");
+        define("alias_test_b", "echo B");
+        assert_eq!(expand("alias_test_a alias_test_b x").as_deref(), Some("echo B  echo B x"));
+    }
+
+    #[test]
+    fn recursion_stops_at_repeated_name() {
+        define("alias_test_ls", "alias_test_ls -l");
+        assert_eq!(expand("alias_test_ls x").as_deref(), Some("\\alias_test_ls -l x"));
+        define("alias_test_e", "echo ");
+        assert_eq!(expand("alias_test_e alias_test_e x").as_deref(), Some("echo  echo  x"));
+        assert_eq!(expand("not_an_alias x"), None);
+    }
+}
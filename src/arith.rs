@@ -0,0 +1,390 @@
+// arith.rs
+
+use crate::expand;
+use crate::vars;
+
+/// Nesting limit for variables whose values are themselves expressions.
+const MAX_DEPTH: usize = 64;
+
+#[derive(Clone, PartialEq, Debug)]
+enum Token {
+    Number(i64),
+    Name(String),
+    Op(&'static str),
+    End,
+}
+
+/// Operators, longest first so that the scanner takes the longest match.
+const OPERATORS: &[&str] = &[
+    "<<=", ">>=", "**", "++", "--", "<<", ">>", "<=", ">=", "==", "!=", "&&", "||",
+    "*=", "/=", "%=", "+=", "-=", "&=", "^=", "|=",
+    "+", "-", "*", "/", "%", "<", ">", "&", "^", "|", "!", "~", "?", ":", "=", "(", ")", ",",
+];
+
+/// Binary operators by precedence level, loosest first. `**` is handled
+/// separately because it associates to the right.
+const BINARY_LEVELS: &[&[&str]] = &[
+    &["|"],
+    &["^"],
+    &["&"],
+    &["==", "!="],
+    &["<=", ">=", "<", ">"],
+    &["<<", ">>"],
+    &["+", "-"],
+    &["*", "/", "%"],
+];
+
+/// Evaluates an arithmetic expression whose parameter expansions and command
+/// substitutions have already been performed.
+pub fn evaluate(expr: &str) -> Result<i64, String> {
+    evaluate_nested(expr, 0)
+}
+
+fn evaluate_nested(expr: &str, depth: usize) -> Result<i64, String> {
+    if depth > MAX_DEPTH {
+        return Err(format!("{}: expression recursion level exceeded", expr));
+    }
+    let mut parser = Parser { text: expr, pos: 0, token: Token::End, token_start: 0, skip: 0, depth };
+    parser.advance().map_err(|e| parser.error(&e))?;
+    if parser.token == Token::End {
+        return Ok(0);
+    }
+    let value = parser.comma().map_err(|e| parser.error(&e))?;
+    if parser.token != Token::End {
+        return Err(parser.error("syntax error in expression"));
+    }
+    Ok(value)
+}
+
+/// A recursive-descent evaluator. Operands on the untaken side of `&&`,
+/// `||` and `?:` are parsed with `skip` raised, which suppresses their side
+/// effects and errors such as division by zero.
+struct Parser<'a> {
+    text: &'a str,
+    pos: usize,
+    token: Token,
+    token_start: usize,
+    skip: usize,
+    depth: usize,
+}
+
+impl Parser<'_> {
+    fn error(&self, message: &str) -> String {
+        let rest = &self.text[self.token_start..];
+        format!("{}: {} (error token is \"{}\")", self.text.trim_start(), message, rest)
+    }
+
+    fn advance(&mut self) -> Result<(), String> {
+        let rest = &self.text[self.pos..];
+        let trimmed = rest.trim_start();
+        self.pos += rest.len() - trimmed.len();
+        if trimmed.is_empty() {
+            // Errors at the end of the input point at the last token.
+            self.token = Token::End;
+            return Ok(());
+        }
+        self.token_start = self.pos;
+        let first = trimmed.chars().next().unwrap_or(' ');
+        if first.is_ascii_digit() {
+            let len = trimmed.find(|c: char| !(c.is_ascii_alphanumeric() || c == '#' || c == '@' || c == '_'))
+                .unwrap_or(trimmed.len());
+            self.token = Token::Number(parse_number(&trimmed[..len])?);
+            self.pos += len;
+        } else if first.is_ascii_alphabetic() || first == '_' {
+            let len = trimmed.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_')).unwrap_or(trimmed.len());
+            self.token = Token::Name(trimmed[..len].to_string());
+            self.pos += len;
+        } else {
+            let op = OPERATORS.iter().find(|op| trimmed.starts_with(**op))
+                .ok_or("syntax error: invalid arithmetic operator")?;
+            self.token = Token::Op(op);
+            self.pos += op.len();
+        }
+        Ok(())
+    }
+
+    fn is_op(&self, op: &str) -> bool {
+        matches!(self.token, Token::Op(o) if o == op)
+    }
+
+    fn expect(&mut self, op: &str) -> Result<(), String> {
+        if !self.is_op(op) {
+            return Err(format!("syntax error: `{}' expected", op));
+        }
+        self.advance()
+    }
+
+    fn comma(&mut self) -> Result<i64, String> {
+        let mut value = self.assignment()?;
+        while self.is_op(",") {
+            self.advance()?;
+            value = self.assignment()?;
+        }
+        Ok(value)
+    }
+
+    fn assignment(&mut self) -> Result<i64, String> {
+        if let Token::Name(name) = self.token.clone() {
+            // Look ahead for an assignment operator after the name.
+            let saved = (self.pos, self.token_start);
+            self.advance()?;
+            if let Token::Op(op) = self.token {
+                if op.ends_with('=') && !matches!(op, "==" | "!=" | "<=" | ">=") {
+                    self.advance()?;
+                    let rhs = self.assignment()?;
+                    let value = match op {
+                        "=" => rhs,
+                        _ => self.binary(&op[..op.len() - 1], self.variable(&name)?, rhs)?,
+                    };
+                    return self.assign(&name, value);
+                }
+            }
+            self.pos = saved.0;
+            self.token_start = saved.1;
+            self.token = Token::Name(name);
+        }
+        self.conditional()
+    }
+
+    fn conditional(&mut self) -> Result<i64, String> {
+        let cond = self.logical_or()?;
+        if !self.is_op("?") {
+            return Ok(cond);
+        }
+        self.advance()?;
+        let then = self.skipping(cond == 0, Self::comma)?;
+        self.expect(":")?;
+        let otherwise = self.skipping(cond != 0, Self::conditional)?;
+        Ok(if cond != 0 { then } else { otherwise })
+    }
+
+    fn logical_or(&mut self) -> Result<i64, String> {
+        let mut value = self.logical_and()?;
+        while self.is_op("||") {
+            self.advance()?;
+            let rhs = self.skipping(value != 0, Self::logical_and)?;
+            value = (value != 0 || rhs != 0) as i64;
+        }
+        Ok(value)
+    }
+
+    fn logical_and(&mut self) -> Result<i64, String> {
+        let mut value = self.binary_level(0)?;
+        while self.is_op("&&") {
+            self.advance()?;
+            let rhs = self.skipping(value == 0, |p| p.binary_level(0))?;
+            value = (value != 0 && rhs != 0) as i64;
+        }
+        Ok(value)
+    }
+
+    fn skipping(&mut self, skip: bool, f: impl FnOnce(&mut Self) -> Result<i64, String>) -> Result<i64, String> {
+        self.skip += skip as usize;
+        let value = f(self);
+        self.skip -= skip as usize;
+        value
+    }
+
+    fn binary_level(&mut self, level: usize) -> Result<i64, String> {
+        let Some(ops) = BINARY_LEVELS.get(level) else {
+            return self.power();
+        };
+        let mut value = self.binary_level(level + 1)?;
+        while let Token::Op(op) = self.token {
+            if !ops.contains(&op) {
+                break;
+            }
+            self.advance()?;
+            let rhs = self.binary_level(level + 1)?;
+            value = self.binary(op, value, rhs)?;
+        }
+        Ok(value)
+    }
+
+    fn power(&mut self) -> Result<i64, String> {
+        let base = self.unary()?;
+        if !self.is_op("**") {
+            return Ok(base);
+        }
+        self.advance()?;
+        let exponent = self.power()?;
+        self.binary("**", base, exponent)
+    }
+
+    fn unary(&mut self) -> Result<i64, String> {
+        let Token::Op(op) = self.token else {
+            return self.postfix();
+        };
+        match op {
+            "-" | "+" | "!" | "~" => {
+                self.advance()?;
+                let value = self.unary()?;
+                Ok(match op {
+                    "-" => value.wrapping_neg(),
+                    "+" => value,
+                    "!" => (value == 0) as i64,
+                    _ => !value,
+                })
+            }
+            "++" | "--" => {
+                self.advance()?;
+                let Token::Name(name) = self.token.clone() else {
+                    return Err("syntax error: operand expected".to_string());
+                };
+                self.advance()?;
+                let value = self.variable(&name)?;
+                self.assign(&name, if op == "++" { value.wrapping_add(1) } else { value.wrapping_sub(1) })
+            }
+            _ => self.postfix(),
+        }
+    }
+
+    fn postfix(&mut self) -> Result<i64, String> {
+        match self.token.clone() {
+            Token::Number(n) => {
+                self.advance()?;
+                Ok(n)
+            }
+            Token::Name(name) => {
+                self.advance()?;
+                let value = self.variable(&name)?;
+                if let Token::Op(op @ ("++" | "--")) = self.token {
+                    self.advance()?;
+                    self.assign(&name, if op == "++" { value.wrapping_add(1) } else { value.wrapping_sub(1) })?;
+                }
+                Ok(value)
+            }
+            Token::Op("(") => {
+                self.advance()?;
+                let value = self.comma()?;
+                self.expect(")")?;
+                Ok(value)
+            }
+            _ => Err("syntax error: operand expected".to_string()),
+        }
+    }
+
+    fn binary(&self, op: &str, lhs: i64, rhs: i64) -> Result<i64, String> {
+        Ok(match op {
+            "+" => lhs.wrapping_add(rhs),
+            "-" => lhs.wrapping_sub(rhs),
+            "*" => lhs.wrapping_mul(rhs),
+            "/" | "%" if rhs == 0 => {
+                if self.skip > 0 {
+                    return Ok(0);
+                }
+                return Err("division by 0".to_string());
+            }
+            "/" => lhs.wrapping_div(rhs),
+            "%" => lhs.wrapping_rem(rhs),
+            "**" if rhs < 0 => {
+                if self.skip > 0 {
+                    return Ok(0);
+                }
+                return Err("exponent less than 0".to_string());
+            }
+            "**" => lhs.wrapping_pow(rhs.min(u32::MAX as i64) as u32),
+            "<<" => lhs.wrapping_shl(rhs as u32),
+            ">>" => lhs.wrapping_shr(rhs as u32),
+            "<" => (lhs < rhs) as i64,
+            ">" => (lhs > rhs) as i64,
+            "<=" => (lhs <= rhs) as i64,
+            ">=" => (lhs >= rhs) as i64,
+            "==" => (lhs == rhs) as i64,
+            "!=" => (lhs != rhs) as i64,
+            "&" => lhs & rhs,
+            "^" => lhs ^ rhs,
+            "|" => lhs | rhs,
+            _ => return Err(format!("syntax error: invalid arithmetic operator `{}'", op)),
+        })
+    }
+
+    /// The value of a variable. Unset and empty variables are 0; any other
+    /// value is evaluated as an expression in its own right.
+    fn variable(&self, name: &str) -> Result<i64, String> {
+        let value = expand::value(name)?;
+        if value.trim().is_empty() {
+            return Ok(0);
+        }
+        evaluate_nested(&value, self.depth + 1)
+    }
+
+    fn assign(&self, name: &str, value: i64) -> Result<i64, String> {
+        if self.skip == 0 {
+            vars::set(name, &value.to_string())?;
+        }
+        Ok(value)
+    }
+}
+
+/// Parses an integer constant: decimal, `0x` hexadecimal, `0` octal, or
+/// `base#digits` for bases 2 to 64.
+fn parse_number(text: &str) -> Result<i64, String> {
+    let (base, digits) = if let Some((base, digits)) = text.split_once('#') {
+        match base.parse::<u32>() {
+            Ok(base @ 2..=64) => (base, digits),
+            _ => return Err("invalid arithmetic base".to_string()),
+        }
+    } else if let Some(hex) = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        (16, hex)
+    } else if text.len() > 1 && text.starts_with('0') {
+        (8, &text[1..])
+    } else {
+        (10, text)
+    };
+    if digits.is_empty() {
+        return Err("invalid number".to_string());
+    }
+    let mut value: i64 = 0;
+    for c in digits.chars() {
+        let digit = match c {
+            '0'..='9' => c as u32 - '0' as u32,
+            'a'..='z' => c as u32 - 'a' as u32 + 10,
+            // Up to base 36 letters are case-insensitive.
+            'A'..='Z' if base <= 36 => c as u32 - 'A' as u32 + 10,
+            'A'..='Z' => c as u32 - 'A' as u32 + 36,
+            '@' => 62,
+            '_' => 63,
+            _ => return Err("invalid number".to_string()),
+        };
+        if digit >= base {
+            return Err("value too great for base".to_string());
+        }
+        value = value.wrapping_mul(base as i64).wrapping_add(digit as i64);
+    }
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn precedence_and_associativity() {
+        assert_eq!(evaluate("1 + 2 * 3"), Ok(7));
+        assert_eq!(evaluate("(1 + 2) * 3"), Ok(9));
+        assert_eq!(evaluate("2 ** 3 ** 2"), Ok(512));
+        assert_eq!(evaluate("10 - 4 - 3"), Ok(3));
+        assert_eq!(evaluate("-3 % 2"), Ok(-1));
+        assert_eq!(evaluate("1 << 4 | 1"), Ok(17));
+        assert_eq!(evaluate("3 > 2 && 2 >= 3"), Ok(0));
+        assert_eq!(evaluate("0 ? 2 : 3"), Ok(3));
+        assert_eq!(evaluate(""), Ok(0));
+    }
+
+    #[test]
+    fn assignments_update_variables() {
+        assert_eq!(evaluate("ARITH_TEST_X = 4, ARITH_TEST_X * 2"), Ok(8));
+        assert_eq!(evaluate("ARITH_TEST_X += 1"), Ok(5));
+        assert_eq!(vars::get("ARITH_TEST_X").as_deref(), Some("5"));
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(evaluate("7 / 0"), Err("7 / 0: division by 0 (error token is \"0\")".to_string()));
+        assert!(evaluate("1 +").is_err());
+        assert!(evaluate("(1").is_err());
+        // The untaken side of `||` is not evaluated.
+        assert_eq!(evaluate("1 || 1 / 0"), Ok(1));
+    }
+}
@@ -0,0 +1,200 @@
+// ast.rs
+
+use std::sync::Arc;
+
+/// How a pipeline is connected to the one before it in an and-or list.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ListOp { Seq, And, Or }
+
+/// A parsed command line: and-or lists separated by `;` or `&`.
+#[derive(Debug)]
+pub struct CommandList {
+    pub and_ors: Vec<AndOr>,
+    pub span: Span,
+}
+
+/// One `&&`/`||` chain of a command list. Each pipeline is paired with the
+/// operator that connects it to the previous one (`Seq` for the first).
+#[derive(Debug)]
+pub struct AndOr {
+    pub pipelines: Vec<(ListOp, Pipeline)>,
+    pub background: bool,
+    pub span: Span,
+}
+
+#[derive(Debug)]
+pub struct Pipeline {
+    pub commands: Vec<Command>,
+    /// Source text, used to describe the pipeline as a job.
+    pub text: String,
+    pub span: Span,
+}
+
+#[derive(Debug)]
+pub enum Command {
+    Simple(SimpleCommand),
+    /// A compound command and the redirections that follow it.
+    Compound { command: CompoundCommand, redirects: Vec<Redirection> },
+    /// `name() body`. The body is shared with the function table once the
+    /// definition has run.
+    FunctionDef { name: String, body: Arc<Command> },
+}
+
+/// A simple command: `NAME=value` prefixes, words and redirections, each
+/// expanded only when the command runs.
+#[derive(Debug)]
+pub struct SimpleCommand {
+    pub assignments: Vec<(String, Word)>,
+    pub words: Vec<Word>,
+    pub redirects: Vec<Redirection>,
+    /// Source text, re-read when the first word is an alias.
+    pub text: String,
+    pub span: Span,
+}
+
+/// A byte range of the text a command was parsed from.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+/// A word, split into the pieces that expand differently.
+#[derive(Debug)]
+pub struct Word {
+    pub parts: Vec<WordPart>,
+    /// Source text, for messages such as "ambiguous redirect".
+    pub text: String,
+    pub span: Span,
+}
+
+#[derive(Debug)]
+pub enum WordPart {
+    /// Unquoted text, in which pattern characters are active.
+    Literal(String),
+    /// Text from single quotes or a backslash escape, taken as is.
+    Quoted(String),
+    /// `"..."`. Its expansions are neither split into fields nor globbed.
+    DoubleQuoted(Vec<WordPart>),
+    /// `$name`, `$1`, `$?` and the other unbraced parameters.
+    Parameter(String),
+    /// The body of `${...}`.
+    Braced(String),
+    /// The command of `$(...)` or `` `...` ``.
+    CommandSubst(String),
+    /// The expression of `$((...))`.
+    Arithmetic(String),
+}
+
+/// One `[n]op target` redirection. `fd` is the explicit descriptor number.
+#[derive(Debug)]
+pub struct Redirection {
+    pub fd: Option<i32>,
+    pub op: RedirectOp,
+    pub target: Word,
+    pub span: Span,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum RedirectOp {
+    /// `<`
+    Input,
+    /// `>`
+    Output,
+    /// `>|`
+    Clobber,
+    /// `>>`
+    Append,
+    /// `<>`
+    ReadWrite,
+    /// `<&`
+    DupInput,
+    /// `>&`
+    DupOutput,
+    /// `&>`
+    OutputAll,
+    /// `&>>`
+    AppendAll,
+    /// `<<`, with the here-document body as its target.
+    HereDoc,
+    /// `<<<`
+    HereString,
+}
+
+impl RedirectOp {
+    /// Operators and their spellings, longest first.
+    pub const ALL: &'static [(&'static str, RedirectOp)] = &[
+        ("<<<", RedirectOp::HereString),
+        ("<<", RedirectOp::HereDoc),
+        ("<>", RedirectOp::ReadWrite),
+        ("<&", RedirectOp::DupInput),
+        ("<", RedirectOp::Input),
+        (">>", RedirectOp::Append),
+        (">&", RedirectOp::DupOutput),
+        (">|", RedirectOp::Clobber),
+        (">", RedirectOp::Output),
+        ("&>>", RedirectOp::AppendAll),
+        ("&>", RedirectOp::OutputAll),
+    ];
+
+    /// The operator at the start of `text`, and its length.
+    pub fn at_start(text: &str) -> Option<(RedirectOp, usize)> {
+        RedirectOp::ALL.iter()
+            .find(|(op, _)| text.starts_with(op))
+            .map(|&(op, kind)| (kind, op.len()))
+    }
+}
+
+#[derive(Debug)]
+pub enum CompoundCommand {
+    /// `{ list; }`, run in the current shell.
+    BraceGroup(CommandList),
+    /// `( list )`, run in a forked copy of the shell. `text` is the source,
+    /// for job listings.
+    Subshell { body: CommandList, text: String },
+    /// `((expr))`, with the expression kept unexpanded.
+    Arithmetic(String),
+    /// `if`/`elif` branches as (condition, body) pairs, and the `else` body.
+    If { branches: Vec<(CommandList, CommandList)>, otherwise: Option<CommandList> },
+    /// `while` loop, or `until` loop if `until` is set.
+    Loop { until: bool, condition: CommandList, body: CommandList },
+    /// `for name [in words]; do body; done`. Without `in`, the loop runs
+    /// over the positional parameters.
+    For { name: String, words: Option<Vec<Word>>, body: CommandList },
+    /// `for ((init; condition; step)); do body; done`.
+    ArithFor { init: String, condition: String, step: String, body: CommandList },
+    Case { word: Word, arms: Vec<CaseArm> },
+}
+
+/// One `pattern | pattern) body ;;` arm of a `case` command.
+#[derive(Debug)]
+pub struct CaseArm {
+    pub patterns: Vec<Word>,
+    pub body: CommandList,
+    pub terminator: CaseTerminator,
+}
+
+/// What happens after a `case` arm's body has run.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CaseTerminator {
+    /// `;;`: the case command is finished.
+    Break,
+    /// `;&`: the next arm's body runs too, without testing its patterns.
+    FallThrough,
+    /// `;;&`: the following arms' patterns are tested as well.
+    Continue,
+}
+
+impl AndOr {
+    /// Source text of the chain, for job listings.
+    pub fn text(&self) -> String {
+        self.pipelines.iter()
+            .map(|(op, pipeline)| match op {
+                ListOp::Seq => pipeline.text.clone(),
+                ListOp::And => format!("&& {}", pipeline.text),
+                ListOp::Or => format!("|| {}", pipeline.text),
+            })
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
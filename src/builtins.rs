@@ -2,21 +2,33 @@
 
 use std::env;
 use std::sync::{Arc, Mutex};
-use crate::util::writeln_ignore_broken_pipe;
+use crate::util::{shell_quote, writeln_ignore_broken_pipe};
+use crate::pipeline::{self, last_status};
 use std::io::Write;
 use std::os::unix::fs::PermissionsExt;
 use std::io::BufRead;
 use std::collections::HashMap;
 use std::sync::OnceLock;
+use crate::vars;
+use crate::options;
+use crate::jobs;
+use crate::functions;
+use crate::alias;
+use crate::script;
+use crate::dirs;
 pub static LAST_A_IDX: OnceLock<Mutex<HashMap<String, usize>>> = OnceLock::new();
 
-pub fn run_builtin(tokens: Vec<String>, history: &Arc<Mutex<Vec<String>>>) {
-    if tokens.is_empty() { return; }
+pub const BUILTINS: &[&str] = &["echo", "exit", "type", "pwd", "cd", "history", "export", "unset", "readonly", "shopt",
+    "jobs", "fg", "bg", "wait", "disown", "set", "break", "continue",
+    "return", "local", "alias", "unalias", "source", ".", "pushd", "popd", "dirs", ":", "true", "false"];
+
+pub fn run_builtin(tokens: Vec<String>, history: &Arc<Mutex<Vec<String>>>) -> i32 {
+    if tokens.is_empty() { return 0; }
     let command = tokens[0].as_str();
     match command {
         "exit" => {
             // Append new history to HISTFILE before exiting
-            if let Ok(histfile) = std::env::var("HISTFILE") {
+            if let Some(histfile) = std::env::var("HISTFILE").ok().filter(|_| options::is_interactive()) {
                 let mut hist = history.lock().unwrap();
                 let this_cmd = tokens.join(" ");
                 let needs_push = hist.last().map(|e| e != &this_cmd).unwrap_or(true);
@@ -33,24 +45,36 @@ pub fn run_builtin(tokens: Vec<String>, history: &Arc<Mutex<Vec<String>>>) {
                     last_idx_map.insert(histfile, hist.len());
                 }
             }
-            std::process::exit(
-                tokens.get(1)
-                    .and_then(|s| s.parse::<i32>().ok())
-                    .unwrap_or(255),
-            )
+            let _ = std::io::stdout().flush();
+            std::process::exit(match tokens.get(1) {
+                Some(code) => code.parse::<i32>().unwrap_or(255),
+                None => last_status(),
+            })
         },
+        ":" | "true" => 0,
+        "false" => 1,
         "echo" => {
             let output = tokens[1..].join(" ");
             let _ = writeln_ignore_broken_pipe(std::io::stdout(), &output);
             let _ = std::io::stdout().flush();
+            0
         }
         "type" => {
             if tokens.len() < 2 {
-                return;
+                return 0;
             }
             match tokens[1].as_str() {
-                "echo" | "exit" | "type" | "pwd" | "cd" | "history" => {
-                    println!("{} is a shell builtin", tokens[1])
+                name if alias::get(name).is_some() => {
+                    let _ = writeln_ignore_broken_pipe(std::io::stdout(), format!("{} is aliased to `{}'", name, alias::get(name).unwrap_or_default()));
+                    0
+                }
+                name if functions::is_function(name) => {
+                    let _ = writeln_ignore_broken_pipe(std::io::stdout(), format!("{} is a function", name));
+                    0
+                }
+                name if BUILTINS.contains(&name) => {
+                    println!("{} is a shell builtin", tokens[1]);
+                    0
                 }
                 _ => {
                     let path = env::var("PATH").unwrap_or_default();
@@ -60,53 +84,33 @@ pub fn run_builtin(tokens: Vec<String>, history: &Arc<Mutex<Vec<String>>>) {
                         if let Ok(metadata) = std::fs::metadata(&full_path) {
                             if metadata.is_file() && metadata.permissions().mode() & 0o111 != 0 {
                                 println!("{} is {}", tokens[1], full_path);
-                                return;
+                                return 0;
                             }
                         }
                     }
-                    println!("{}: not found", tokens[1])
+                    println!("{}: not found", tokens[1]);
+                    1
                 }
             }
         }
-        "pwd" => {
-            let current = env::current_dir();
-            match current {
-                Ok(path) => { let _ = writeln_ignore_broken_pipe(std::io::stdout(), &format!("{}", path.display())); },
-                Err(_e) => { let _ = writeln_ignore_broken_pipe(std::io::stdout(), &format!("{}: command not found", command)); },
-            }
-        }
-        "cd" => {
-            if tokens.len() < 2 {
-                let _ = writeln_ignore_broken_pipe(std::io::stdout(), "cd: missing argument");
-                return;
-            }
-            let mut target = tokens[1].to_string();
-            if target == "~" || target.starts_with("~/") {
-                if let Some(home) = env::var_os("HOME") {
-                    if target == "~" {
-                        target = home.to_string_lossy().to_string();
-                    } else {
-                        target = format!("{}/{}", home.to_string_lossy(), &target[2..]);
-                    }
-                }
-            }
-            if let Err(_) = env::set_current_dir(&target) {
-                let _ = writeln_ignore_broken_pipe(std::io::stdout(), &format!("cd: {}: No such file or directory", tokens[1]));
-            }
-        }
+        "pwd" => dirs::builtin_pwd(&tokens[1..]),
+        "cd" => dirs::builtin_cd(&tokens[1..]),
+        "pushd" => dirs::builtin_pushd(&tokens[1..]),
+        "popd" => dirs::builtin_popd(&tokens[1..]),
+        "dirs" => dirs::builtin_dirs(&tokens[1..]),
         "history" => {
             if tokens.len() == 3 && tokens[1] == "-r" {
                 let path = &tokens[2];
                 if let Ok(file) = std::fs::File::open(path) {
                     let reader = std::io::BufReader::new(file);
                     let mut hist = history.lock().unwrap();
-                    for line in reader.lines().flatten() {
+                    for line in reader.lines().map_while(Result::ok) {
                         if !line.trim().is_empty() {
                             hist.push(line);
                         }
                     }
                 }
-                return;
+                return 0;
             }
             // Implement history -w <file>
             if tokens.len() == 3 && tokens[1] == "-w" {
@@ -121,15 +125,15 @@ pub fn run_builtin(tokens: Vec<String>, history: &Arc<Mutex<Vec<String>>>) {
                 let mut file = match std::fs::File::create(path) {
                     Ok(f) => f,
                     Err(e) => {
-                        let _ = writeln_ignore_broken_pipe(std::io::stdout(), &format!("history: cannot write: {}", e));
-                        return;
+                        let _ = writeln_ignore_broken_pipe(std::io::stdout(), format!("history: cannot write: {}", e));
+                        return 1;
                     }
                 };
                 for entry in hist.iter() {
                     let _ = writeln!(file, "{}", entry);
                 }
                 // Ensure trailing newline (already added by writeln!)
-                return;
+                return 0;
             }
             // Implement history -a <file>
             if tokens.len() == 3 && tokens[1] == "-a" {
@@ -141,13 +145,14 @@ pub fn run_builtin(tokens: Vec<String>, history: &Arc<Mutex<Vec<String>>>) {
                 if needs_push {
                     hist.push(this_cmd.clone());
                 }
+                let last_a_idx = LAST_A_IDX.get_or_init(|| Mutex::new(HashMap::new()));
                 let mut last_idx_map = last_a_idx.lock().unwrap();
                 let start = *last_idx_map.get(&path).unwrap_or(&0);
                 let mut file = match std::fs::OpenOptions::new().create(true).append(true).open(&path) {
                     Ok(f) => f,
                     Err(e) => {
-                        let _ = writeln_ignore_broken_pipe(std::io::stdout(), &format!("history: cannot append: {}", e));
-                        return;
+                        let _ = writeln_ignore_broken_pipe(std::io::stdout(), format!("history: cannot append: {}", e));
+                        return 1;
                     }
                 };
                 for entry in hist.iter().skip(start) {
@@ -155,23 +160,274 @@ pub fn run_builtin(tokens: Vec<String>, history: &Arc<Mutex<Vec<String>>>) {
                 }
                 last_idx_map.insert(path, hist.len());
                 // Ensure trailing newline (already added by writeln!)
-                return;
+                return 0;
             }
             let hist = history.lock().unwrap();
             if tokens.len() == 2 {
                 if let Ok(n) = tokens[1].parse::<usize>() {
                     let total = hist.len();
-                    let start = if n > total { 0 } else { total - n };
+                    let start = total.saturating_sub(n);
                     for (i, cmd) in hist.iter().enumerate().skip(start) {
                         println!("{:>5}  {}", i + 1, cmd);
                     }
-                    return;
+                    return 0;
                 }
             }
             for (i, cmd) in hist.iter().enumerate() {
                 println!("{:>5}  {}", i + 1, cmd);
             }
+            0
+        }
+        "export" => {
+            let mut status = 0;
+            let mut unexport = false;
+            let mut names = Vec::new();
+            for arg in &tokens[1..] {
+                match arg.as_str() {
+                    "-n" => unexport = true,
+                    "-p" => {}
+                    _ => names.push(arg.as_str()),
+                }
+            }
+            if names.is_empty() {
+                print_declarations("-x", vars::exported_vars());
+                return 0;
+            }
+            for arg in names {
+                let (name, value) = match arg.split_once('=') {
+                    Some((name, value)) => (name, Some(value)),
+                    None => (arg, None),
+                };
+                if !vars::is_valid_name(name) {
+                    eprintln!("export: `{}': not a valid identifier", arg);
+                    status = 1;
+                    continue;
+                }
+                if let Some(value) = value {
+                    if let Err(e) = vars::set(name, value) {
+                        eprintln!("export: {}", e);
+                        status = 1;
+                        continue;
+                    }
+                }
+                if unexport { vars::unexport(name) } else { vars::export(name) }
+            }
+            status
+        }
+        "readonly" => {
+            let mut status = 0;
+            let names: Vec<&String> = tokens[1..].iter().filter(|a| a.as_str() != "-p").collect();
+            if names.is_empty() {
+                print_declarations("-r", vars::readonly_vars());
+                return 0;
+            }
+            for arg in names {
+                let (name, value) = match arg.split_once('=') {
+                    Some((name, value)) => (name, Some(value)),
+                    None => (arg.as_str(), None),
+                };
+                if !vars::is_valid_name(name) {
+                    eprintln!("readonly: `{}': not a valid identifier", arg);
+                    status = 1;
+                    continue;
+                }
+                if let Some(value) = value {
+                    if let Err(e) = vars::set(name, value) {
+                        eprintln!("readonly: {}", e);
+                        status = 1;
+                        continue;
+                    }
+                }
+                vars::set_readonly(name);
+            }
+            status
+        }
+        "unset" => {
+            let mut status = 0;
+            let functions_only = tokens.get(1).is_some_and(|a| a == "-f");
+            for name in tokens[1..].iter().filter(|a| !matches!(a.as_str(), "-v" | "-f")) {
+                if functions_only {
+                    functions::unset(name);
+                } else if vars::get(name).is_none() && functions::unset(name) {
+                    // With no such variable, `unset` removes a function.
+                } else if !vars::is_valid_name(name) {
+                    eprintln!("unset: `{}': not a valid identifier", name);
+                    status = 1;
+                } else if let Err(e) = vars::unset(name) {
+                    eprintln!("unset: {}", e);
+                    status = 1;
+                }
+            }
+            status
+        }
+        "shopt" => {
+            let mut mode = None;
+            let mut quiet = false;
+            let mut names = Vec::new();
+            for arg in &tokens[1..] {
+                match arg.as_str() {
+                    "-s" => mode = Some(true),
+                    "-u" => mode = Some(false),
+                    "-q" => quiet = true,
+                    _ => names.push(arg.as_str()),
+                }
+            }
+            if let Some(on) = mode {
+                let mut status = 0;
+                for name in names {
+                    if let Err(e) = options::set_shopt(name, on) {
+                        eprintln!("shopt: {}", e);
+                        status = 1;
+                    }
+                }
+                return status;
+            }
+            if names.is_empty() {
+                names = options::SHOPT_OPTIONS.to_vec();
+            }
+            let mut status = 0;
+            for name in names {
+                if !options::SHOPT_OPTIONS.contains(&name) {
+                    eprintln!("shopt: {}: invalid shell option name", name);
+                    status = 1;
+                    continue;
+                }
+                let on = options::shopt_enabled(name);
+                if !on {
+                    status = 1;
+                }
+                if !quiet {
+                    let state = if on { "on" } else { "off" };
+                    let _ = writeln_ignore_broken_pipe(std::io::stdout(), format!("{:<15}\t{}", name, state));
+                }
+            }
+            status
+        }
+        "set" => {
+            let args = &tokens[1..];
+            if args.is_empty() {
+                for (name, value) in vars::all_vars() {
+                    let value = shell_quote(&value.unwrap_or_default());
+                    let _ = writeln_ignore_broken_pipe(std::io::stdout(), format!("{}={}", name, value));
+                }
+                return 0;
+            }
+            if let [flag] = args {
+                if flag == "-o" || flag == "+o" {
+                    for &(_, name) in options::SET_OPTIONS {
+                        let on = options::option_enabled(name);
+                        let line = if flag == "-o" {
+                            format!("{:<15}\t{}", name, if on { "on" } else { "off" })
+                        } else {
+                            format!("set {}o {}", if on { '-' } else { '+' }, name)
+                        };
+                        let _ = writeln_ignore_broken_pipe(std::io::stdout(), line);
+                    }
+                    return 0;
+                }
+            }
+            match options::parse_set_flags(args) {
+                Ok(i) => {
+                    if i < args.len() || (i > 0 && args[i - 1] == "--") {
+                        vars::set_positional(args[i..].to_vec());
+                    }
+                    0
+                }
+                Err(e) => {
+                    eprintln!("set: {}", e);
+                    2
+                }
+            }
+        }
+        "jobs" => jobs::builtin_jobs(&tokens[1..]),
+        "fg" => jobs::builtin_fg(&tokens[1..]),
+        "bg" => jobs::builtin_bg(&tokens[1..]),
+        "wait" => jobs::builtin_wait(&tokens[1..]),
+        "disown" => jobs::builtin_disown(&tokens[1..]),
+        "break" | "continue" => pipeline::builtin_break(command, &tokens[1..]),
+        "return" => pipeline::builtin_return(&tokens[1..]),
+        "source" | "." => script::builtin_source(command, &tokens[1..]),
+        "alias" => {
+            let args: Vec<&String> = tokens[1..].iter().filter(|a| a.as_str() != "-p").collect();
+            if args.is_empty() {
+                for (name, value) in alias::all() {
+                    let _ = writeln_ignore_broken_pipe(std::io::stdout(), alias::definition(&name, &value));
+                }
+                return 0;
+            }
+            let mut status = 0;
+            for arg in args {
+                match arg.split_once('=') {
+                    Some((name, _)) if !alias::is_valid_name(name) => {
+                        eprintln!("alias: `{}': invalid alias name", name);
+                        status = 1;
+                    }
+                    Some((name, value)) => alias::define(name, value),
+                    None => match alias::get(arg) {
+                        Some(value) => {
+                            let _ = writeln_ignore_broken_pipe(std::io::stdout(), alias::definition(arg, &value));
+                        }
+                        None => {
+                            eprintln!("alias: {}: not found", arg);
+                            status = 1;
+                        }
+                    },
+                }
+            }
+            status
+        }
+        "unalias" => {
+            if tokens.get(1).is_some_and(|a| a == "-a") {
+                alias::clear();
+                return 0;
+            }
+            if tokens.len() < 2 {
+                eprintln!("unalias: usage: unalias [-a] name [name ...]");
+                return 2;
+            }
+            let mut status = 0;
+            for name in &tokens[1..] {
+                if !alias::remove(name) {
+                    eprintln!("unalias: {}: not found", name);
+                    status = 1;
+                }
+            }
+            status
+        }
+        "local" => {
+            let mut status = 0;
+            for arg in &tokens[1..] {
+                let (name, value) = match arg.split_once('=') {
+                    Some((name, value)) => (name, Some(value)),
+                    None => (arg.as_str(), None),
+                };
+                if !vars::is_valid_name(name) {
+                    eprintln!("local: `{}': not a valid identifier", arg);
+                    status = 1;
+                } else if let Err(e) = vars::declare_local(name, value) {
+                    eprintln!("local: {}", e);
+                    status = 1;
+                }
+            }
+            status
         }
         _ => unreachable!(),
     }
+}
+
+/// Prints variables in the reusable `declare -x NAME="value"` form.
+fn print_declarations(flag: &str, vars: Vec<(String, Option<String>)>) {
+    for (name, value) in vars {
+        let line = match value {
+            Some(value) => {
+                let escaped: String = value.chars().flat_map(|c| {
+                    let escape = matches!(c, '"' | '\\' | '$' | '`');
+                    escape.then_some('\\').into_iter().chain([c])
+                }).collect();
+                format!("declare {} {}=\"{}\"", flag, name, escaped)
+            }
+            None => format!("declare {} {}", flag, name),
+        };
+        let _ = writeln_ignore_broken_pipe(std::io::stdout(), line);
+    }
 } 
\ No newline at end of file
@@ -8,6 +8,7 @@ use rustyline::validate::{Validator, ValidationContext, ValidationResult};
 use rustyline::error::ReadlineError;
 use std::cell::RefCell;
 use std::os::unix::fs::PermissionsExt;
+use crate::builtins::BUILTINS;
 
 pub struct BuiltinCompleter {
     pub last_prefix: RefCell<String>,
@@ -30,7 +31,7 @@ impl Completer for BuiltinCompleter {
     fn complete(&self, line: &str, pos: usize, _ctx: &Context<'_>) -> Result<(usize, Vec<Pair>), ReadlineError> {
         let prefix = &line[..pos];
         let mut names = Vec::new();
-        for b in ["echo", "exit", "type", "pwd", "cd", "history"] {
+        for b in BUILTINS {
             if b.starts_with(prefix) {
                 names.push(b.to_string());
             }
@@ -92,6 +93,9 @@ impl Hinter for BuiltinCompleter {
 
 impl Highlighter for BuiltinCompleter {}
 
+/// Every line is accepted as typed: when a command is unfinished, the REPL
+/// reads its continuation lines itself with the `$PS2` prompt, the same way
+/// as here-document bodies, since rustyline has no continuation prompt.
 impl Validator for BuiltinCompleter {
     fn validate(&self, _ctx: &mut ValidationContext) -> Result<ValidationResult, ReadlineError> {
         Ok(ValidationResult::Valid(None))
@@ -0,0 +1,547 @@
+// dirs.rs
+
+use crate::options;
+use crate::spell;
+use crate::util::writeln_ignore_broken_pipe;
+use crate::vars;
+use nix::errno::Errno;
+use std::env;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+use std::sync::{Mutex, MutexGuard, OnceLock};
+
+/// The directory stack of `pushd` and `popd`, without the current
+/// directory, which is always the top entry.
+static STACK: OnceLock<Mutex<Vec<String>>> = OnceLock::new();
+
+fn stack() -> MutexGuard<'static, Vec<String>> {
+    STACK.get_or_init(|| Mutex::new(Vec::new())).lock().unwrap()
+}
+
+/// Sets `PWD` at startup. An inherited value is kept if it still names the
+/// current directory, since it may reach it through symlinks.
+pub fn init() {
+    if logical_pwd().is_none() {
+        if let Ok(dir) = physical_pwd() {
+            vars::set("PWD", &dir).ok();
+        }
+    }
+    vars::export("PWD");
+}
+
+/// `$PWD`, if it is an absolute name of the current directory without `.`
+/// or `..` components.
+fn logical_pwd() -> Option<String> {
+    let pwd = vars::get("PWD")?;
+    let plain = pwd.starts_with('/') && pwd.split('/').all(|part| part != "." && part != "..");
+    let same = |a: &std::fs::Metadata, b: &std::fs::Metadata| a.dev() == b.dev() && a.ino() == b.ino();
+    match (std::fs::metadata(&pwd), std::fs::metadata(".")) {
+        (Ok(a), Ok(b)) if plain && same(&a, &b) => Some(pwd),
+        _ => None,
+    }
+}
+
+/// The current directory with all symlinks resolved.
+fn physical_pwd() -> Result<String, String> {
+    env::current_dir()
+        .map(|dir| dir.to_string_lossy().into_owned())
+        .map_err(|e| describe(&e))
+}
+
+/// The current directory as the shell knows it: `$PWD` when it is valid.
+pub fn current_dir() -> Result<String, String> {
+    logical_pwd().map_or_else(physical_pwd, Ok)
+}
+
+/// The message for a failed system call, without Rust's "(os error N)".
+fn describe(e: &std::io::Error) -> String {
+    match e.raw_os_error() {
+        Some(code) => Errno::from_i32(code).desc().to_string(),
+        None => e.to_string(),
+    }
+}
+
+/// Removes `.` components, and `..` along with the component before it,
+/// from an absolute path without consulting the file system.
+fn canonicalize(path: &str) -> String {
+    let mut parts: Vec<&str> = Vec::new();
+    for part in path.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => { parts.pop(); }
+            _ => parts.push(part),
+        }
+    }
+    format!("/{}", parts.join("/"))
+}
+
+/// Changes the current directory to `dir` and updates `PWD` and `OLDPWD`.
+/// Logically, `..` in `dir` removes the component of `$PWD` before it, so
+/// that it goes back through a symlink the way it came; with `physical`
+/// set, symlinks are resolved and `PWD` names the real directory.
+pub fn change_dir(dir: &str, physical: bool) -> Result<(), String> {
+    let old = current_dir().ok();
+    let new = if physical {
+        env::set_current_dir(dir).map_err(|e| format!("{}: {}", dir, describe(&e)))?;
+        physical_pwd()?
+    } else {
+        let path = match &old {
+            Some(old) if !dir.starts_with('/') => format!("{}/{}", old, dir),
+            _ => dir.to_string(),
+        };
+        let path = canonicalize(&path);
+        match env::set_current_dir(&path) {
+            Ok(()) => path,
+            // Fall back to the name as given, e.g. when `$PWD` is stale.
+            Err(e) => match env::set_current_dir(dir) {
+                Ok(()) => physical_pwd()?,
+                Err(_) => return Err(format!("{}: {}", dir, describe(&e))),
+            },
+        }
+    };
+    if let Some(old) = old {
+        vars::set("OLDPWD", &old).ok();
+        vars::export("OLDPWD");
+    }
+    vars::set("PWD", &new).ok();
+    Ok(())
+}
+
+/// Looks a relative directory name up in `$CDPATH`. Returns the path to
+/// use, and whether it came from a non-empty entry, in which case `cd`
+/// prints where it went.
+fn search_cdpath(dir: &str) -> Option<(String, bool)> {
+    let explicit = dir.starts_with('/') || dir == "." || dir == ".."
+        || dir.starts_with("./") || dir.starts_with("../");
+    if explicit {
+        return None;
+    }
+    let cdpath = vars::get("CDPATH")?;
+    cdpath.split(':')
+        .map(|entry| match entry {
+            "" => (dir.to_string(), false),
+            _ => (format!("{}/{}", entry.trim_end_matches('/'), dir), true),
+        })
+        .find(|(path, _)| Path::new(path).is_dir())
+}
+
+/// Prints a usage error for `arg` and returns the status for it.
+fn usage_error(name: &str, arg: &str, problem: &str, usage: &str) -> i32 {
+    eprintln!("{}: {}: {}", name, arg, problem);
+    eprintln!("{}: usage: {}", name, usage);
+    2
+}
+
+/// Parses the `-L` and `-P` options of `cd` and `pwd`. Returns whether `-P`
+/// won, and the remaining operands, or prints a usage error.
+fn parse_options<'a>(name: &str, usage: &str, args: &'a [String]) -> Result<(bool, &'a [String]), i32> {
+    let mut physical = false;
+    let mut rest = args;
+    while let Some(arg) = rest.first() {
+        if arg == "--" {
+            rest = &rest[1..];
+            break;
+        }
+        let Some(flags) = arg.strip_prefix('-').filter(|flags| !flags.is_empty()) else {
+            break;
+        };
+        for flag in flags.chars() {
+            match flag {
+                'L' => physical = false,
+                'P' => physical = true,
+                _ => return Err(usage_error(name, &format!("-{}", flag), "invalid option", usage)),
+            }
+        }
+        rest = &rest[1..];
+    }
+    Ok((physical, rest))
+}
+
+/// The `cd` builtin. Without an operand it goes to `$HOME`; `cd -` goes to
+/// `$OLDPWD` and prints it.
+pub fn builtin_cd(args: &[String]) -> i32 {
+    let (physical, operands) = match parse_options("cd", "cd [-L|-P] [dir]", args) {
+        Ok(parsed) => parsed,
+        Err(status) => return status,
+    };
+    if operands.len() > 1 {
+        eprintln!("cd: too many arguments");
+        return 1;
+    }
+    let (dir, print) = match operands.first().map(String::as_str) {
+        None => match vars::get("HOME") {
+            Some(home) => (home, false),
+            None => {
+                eprintln!("cd: HOME not set");
+                return 1;
+            }
+        },
+        Some("-") => match vars::get("OLDPWD") {
+            Some(old) => (old, true),
+            None => {
+                eprintln!("cd: OLDPWD not set");
+                return 1;
+            }
+        },
+        Some(dir) => (dir.to_string(), false),
+    };
+    if dir.is_empty() {
+        return 0;
+    }
+    cd_to("cd", &dir, physical, print)
+}
+
+/// Changes to an operand of `cd` or `pushd`, looking it up in `$CDPATH` or
+/// correcting it with `cdspell`. Prints the new directory if a `$CDPATH`
+/// entry was used or `print` is set.
+fn cd_to(name: &str, dir: &str, physical: bool, mut print: bool) -> i32 {
+    let mut target = dir.to_string();
+    if let Some((path, from_entry)) = search_cdpath(dir) {
+        target = path;
+        print |= from_entry;
+    } else if options::shopt_enabled("cdspell") {
+        if let Some(suggestion) = spell::suggest_dir(dir) {
+            if spell::confirm(name, dir, &suggestion) {
+                let _ = writeln_ignore_broken_pipe(std::io::stdout(), &suggestion);
+                target = suggestion;
+            }
+        }
+    }
+    if let Err(e) = change_dir(&target, physical) {
+        eprintln!("{}: {}", name, e);
+        return 1;
+    }
+    if print {
+        let _ = writeln_ignore_broken_pipe(std::io::stdout(), vars::get("PWD").unwrap_or_default());
+    }
+    0
+}
+
+/// The `pwd` builtin: prints `$PWD`, or with `-P` the directory with
+/// symlinks resolved.
+pub fn builtin_pwd(args: &[String]) -> i32 {
+    let physical = match parse_options("pwd", "pwd [-LP]", args) {
+        Ok((physical, _)) => physical,
+        Err(status) => return status,
+    };
+    let dir = if physical { physical_pwd() } else { current_dir() };
+    match dir {
+        Ok(dir) => {
+            let _ = writeln_ignore_broken_pipe(std::io::stdout(), dir);
+            0
+        }
+        Err(e) => {
+            eprintln!("pwd: error retrieving current directory: {}", e);
+            1
+        }
+    }
+}
+
+/// The whole directory stack as `dirs` lists it, the current directory
+/// first.
+fn full_stack() -> Vec<String> {
+    let mut dirs = vec![current_dir().unwrap_or_default()];
+    dirs.extend(stack().iter().cloned());
+    dirs
+}
+
+/// Parses a stack index: `+N` counts from zero at the left of the list
+/// `dirs` prints and `-N` from the right. The sign is optional when
+/// `signed` is unset. Returns whether it counts from the right, and `N`.
+fn parse_index(arg: &str, signed: bool) -> Option<(bool, usize)> {
+    let (from_right, digits) = match arg.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => match arg.strip_prefix('+') {
+            Some(digits) => (false, digits),
+            None if !signed => (false, arg),
+            None => return None,
+        },
+    };
+    if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    Some((from_right, digits.parse().ok()?))
+}
+
+/// The position in a stack of `len` entries that an index names.
+fn resolve((from_right, n): (bool, usize), len: usize) -> Option<usize> {
+    match n < len {
+        true if from_right => Some(len - 1 - n),
+        true => Some(n),
+        false => None,
+    }
+}
+
+/// The stack entry for a tilde-prefix `~N`, `~+N` or `~-N`, or `None` if
+/// `name` is not one or is out of range.
+pub fn tilde_entry(name: &str) -> Option<String> {
+    let dirs = full_stack();
+    let index = resolve(parse_index(name, false)?, dirs.len())?;
+    Some(dirs[index].clone())
+}
+
+/// Whether `name` is the index of a tilde-prefix `~N`, `~+N` or `~-N`.
+pub fn is_tilde_index(name: &str) -> bool {
+    parse_index(name, false).is_some()
+}
+
+/// Shortens a directory under `$HOME` to start with `~`.
+fn abbreviate(dir: &str) -> String {
+    let home = vars::get("HOME").filter(|home| !home.is_empty() && home != "/");
+    match home.as_deref().and_then(|home| dir.strip_prefix(home)) {
+        Some(rest) if rest.is_empty() || rest.starts_with('/') => format!("~{}", rest),
+        _ => dir.to_string(),
+    }
+}
+
+/// Prints the stack on one line, or with `lines` set one entry per line,
+/// numbered if `numbered` is set. With `long` set, `$HOME` is not shortened.
+fn print_stack(dirs: &[String], long: bool, lines: bool, numbered: bool) {
+    let shown: Vec<String> = dirs.iter()
+        .map(|dir| if long { dir.clone() } else { abbreviate(dir) })
+        .collect();
+    let output = match (lines, numbered) {
+        (_, true) => shown.iter().enumerate().map(|(i, dir)| format!("{:2}  {}", i, dir)).collect::<Vec<_>>().join("\n"),
+        (true, false) => shown.join("\n"),
+        (false, false) => shown.join(" "),
+    };
+    let _ = writeln_ignore_broken_pipe(std::io::stdout(), output);
+}
+
+/// The `dirs` builtin: lists the directory stack, or clears it with `-c`.
+pub fn builtin_dirs(args: &[String]) -> i32 {
+    let usage = "dirs [-clpv] [+N] [-N]";
+    let (mut clear, mut long, mut lines, mut numbered) = (false, false, false, false);
+    let mut index = None;
+    for arg in args {
+        match arg.as_str() {
+            "-c" => clear = true,
+            "-l" => long = true,
+            "-p" => lines = true,
+            "-v" => numbered = true,
+            _ if arg.starts_with(['+', '-']) => match parse_index(arg, true) {
+                Some(parsed) => index = Some(parsed),
+                None => return usage_error("dirs", arg, "invalid number", usage),
+            },
+            _ => return usage_error("dirs", arg, "invalid option", usage),
+        }
+    }
+    if clear {
+        stack().clear();
+        return 0;
+    }
+    let dirs = full_stack();
+    match index {
+        None => print_stack(&dirs, long, lines, numbered),
+        Some(parsed) => match resolve(parsed, dirs.len()) {
+            Some(i) => print_stack(&dirs[i..=i], long, false, false),
+            None if dirs.len() == 1 => {
+                eprintln!("dirs: directory stack empty");
+                return 1;
+            }
+            None => {
+                eprintln!("dirs: {}: directory stack index out of range", parsed.1);
+                return 1;
+            }
+        },
+    }
+    0
+}
+
+/// Parses the `-n` option and the single operand of `pushd` and `popd`.
+/// Arguments other than `dir_allowed` operands that start with `-` must be
+/// stack indexes.
+fn parse_stack_args<'a>(name: &str, usage: &str, args: &'a [String], dir_allowed: bool)
+    -> Result<(bool, Option<&'a str>), i32> {
+    let mut no_cd = false;
+    let mut operands = Vec::new();
+    let mut rest = args.iter();
+    while let Some(arg) = rest.next() {
+        match arg.as_str() {
+            "-n" => no_cd = true,
+            "--" => {
+                operands.extend(rest.by_ref().map(String::as_str));
+            }
+            _ if parse_index(arg, true).is_some() => operands.push(arg.as_str()),
+            _ if arg.starts_with('-') && arg.len() > 1 => return Err(usage_error(name, arg, "invalid number", usage)),
+            _ if !dir_allowed => return Err(usage_error(name, arg, "invalid argument", usage)),
+            _ => operands.push(arg.as_str()),
+        }
+    }
+    if operands.len() > 1 {
+        eprintln!("{}: too many arguments", name);
+        return Err(1);
+    }
+    Ok((no_cd, operands.first().copied()))
+}
+
+/// The `pushd` builtin. `pushd dir` changes to `dir` and pushes the old
+/// directory; `pushd +N` rotates the stack to bring entry `N` to the top;
+/// a bare `pushd` swaps the top two entries. `-n` adds `dir` without
+/// changing directory.
+pub fn builtin_pushd(args: &[String]) -> i32 {
+    let (no_cd, operand) = match parse_stack_args("pushd", "pushd [-n] [+N | -N | dir]", args, true) {
+        Ok(parsed) => parsed,
+        Err(status) => return status,
+    };
+    let old = current_dir().unwrap_or_default();
+    match operand {
+        None => {
+            let Some(top) = stack().first().cloned() else {
+                eprintln!("pushd: no other directory");
+                return 1;
+            };
+            if !no_cd {
+                if let Err(e) = change_dir(&top, false) {
+                    eprintln!("pushd: {}", e);
+                    return 1;
+                }
+                stack()[0] = old;
+            }
+        }
+        Some(arg) if parse_index(arg, true).is_some() => {
+            let mut dirs = full_stack();
+            if dirs.len() == 1 {
+                eprintln!("pushd: directory stack empty");
+                return 1;
+            }
+            let Some(i) = resolve(parse_index(arg, true).unwrap(), dirs.len()) else {
+                eprintln!("pushd: {}: directory stack index out of range", arg);
+                return 1;
+            };
+            dirs.rotate_left(i);
+            if let Err(e) = change_dir(&dirs[0], false) {
+                eprintln!("pushd: {}", e);
+                return 1;
+            }
+            *stack() = dirs.split_off(1);
+        }
+        Some(dir) if no_cd => stack().insert(0, dir.to_string()),
+        Some(dir) => {
+            if cd_to("pushd", dir, false, false) != 0 {
+                return 1;
+            }
+            stack().insert(0, old);
+        }
+    }
+    print_stack(&full_stack(), false, false, false);
+    0
+}
+
+/// The `popd` builtin. Removes the top entry and changes to the next one,
+/// or with `+N` or `-N` removes that entry. `-n` removes the entry below
+/// the top without changing directory.
+pub fn builtin_popd(args: &[String]) -> i32 {
+    let usage = "popd [-n] [+N | -N]";
+    let (no_cd, operand) = match parse_stack_args("popd", usage, args, false) {
+        Ok(parsed) => parsed,
+        Err(status) => return status,
+    };
+    let dirs = full_stack();
+    if dirs.len() == 1 {
+        eprintln!("popd: directory stack empty");
+        return 1;
+    }
+    let index = match operand {
+        None => 0,
+        Some(arg) => match resolve(parse_index(arg, true).unwrap(), dirs.len()) {
+            Some(i) => i,
+            None => {
+                eprintln!("popd: {}: directory stack index out of range", arg);
+                return 1;
+            }
+        },
+    };
+    if index == 0 && !no_cd {
+        if let Err(e) = change_dir(&dirs[1], false) {
+            eprintln!("popd: {}", e);
+            return 1;
+        }
+    }
+    stack().remove(index.max(1) - 1);
+    print_stack(&full_stack(), false, false, false);
+    0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(args: &[&str]) -> Vec<String> {
+        args.iter().map(|a| a.to_string()).collect()
+    }
+
+    #[test]
+    fn canonicalize_drops_dot_and_dot_dot() {
+        assert_eq!(canonicalize("/a/./b//c/"), "/a/b/c");
+        assert_eq!(canonicalize("/a/link/../b"), "/a/b");
+        assert_eq!(canonicalize("/../.."), "/");
+    }
+
+    #[test]
+    fn cd_options() {
+        let parse = |args: &[&str]| {
+            let args = strings(args);
+            parse_options("cd", "cd [-L|-P] [dir]", &args).map(|(physical, rest)| (physical, rest.to_vec()))
+        };
+        assert_eq!(parse(&["-P", "dir"]), Ok((true, strings(&["dir"]))));
+        assert_eq!(parse(&["-PL"]), Ok((false, vec![])));
+        assert_eq!(parse(&["-P", "--", "-L"]), Ok((true, strings(&["-L"]))));
+        assert_eq!(parse(&["-"]), Ok((false, strings(&["-"]))));
+        assert_eq!(parse(&["-x"]), Err(2));
+    }
+
+    #[test]
+    fn cdpath_lookup() {
+        let base = std::env::temp_dir().join(format!("cdpath_test_{}", std::process::id()));
+        std::fs::create_dir_all(base.join("two/cdpath_target")).unwrap();
+        let base = base.to_str().unwrap().to_string();
+        vars::set("CDPATH", &format!("{0}/one:{0}/two/", base)).unwrap();
+        assert_eq!(search_cdpath("cdpath_target"), Some((format!("{}/two/cdpath_target", base), true)));
+        assert_eq!(search_cdpath("missing"), None);
+        assert_eq!(search_cdpath("./cdpath_target"), None);
+        // An empty entry is the current directory, where there is no match.
+        vars::set("CDPATH", &format!(":{}/two", base)).unwrap();
+        assert_eq!(search_cdpath("cdpath_target").map(|(_, print)| print), Some(true));
+        assert_eq!(search_cdpath("."), None);
+        vars::unset("CDPATH").unwrap();
+        std::fs::remove_dir_all(&base).unwrap();
+    }
+
+    #[test]
+    fn stack_indexes() {
+        assert_eq!(parse_index("+2", true), Some((false, 2)));
+        assert_eq!(parse_index("-0", true), Some((true, 0)));
+        assert_eq!(parse_index("2", true), None);
+        assert_eq!(parse_index("2", false), Some((false, 2)));
+        assert_eq!(parse_index("+", false), None);
+        assert_eq!(parse_index("-x", true), None);
+        assert_eq!(resolve((false, 1), 3), Some(1));
+        assert_eq!(resolve((true, 0), 3), Some(2));
+        assert_eq!(resolve((true, 3), 3), None);
+    }
+
+    #[test]
+    fn pushd_and_popd_arguments() {
+        let parse = |args: &[&str], dir_allowed: bool| {
+            let args = strings(args);
+            parse_stack_args("pushd", "pushd [-n] [+N | -N | dir]", &args, dir_allowed)
+                .map(|(no_cd, operand)| (no_cd, operand.map(str::to_string)))
+        };
+        assert_eq!(parse(&["-n", "+1"], true), Ok((true, Some("+1".to_string()))));
+        assert_eq!(parse(&["--", "-dir"], true), Ok((false, Some("-dir".to_string()))));
+        assert_eq!(parse(&["dir"], true), Ok((false, Some("dir".to_string()))));
+        assert_eq!(parse(&["dir"], false), Err(2));
+        assert_eq!(parse(&["-q"], true), Err(2));
+        assert_eq!(parse(&["a", "b"], true), Err(1));
+    }
+
+    #[test]
+    fn home_is_shown_as_tilde() {
+        vars::set("HOME", "/home/dirs_test").unwrap();
+        assert_eq!(abbreviate("/home/dirs_test"), "~");
+        assert_eq!(abbreviate("/home/dirs_test/src"), "~/src");
+        assert_eq!(abbreviate("/home/dirs_test2"), "/home/dirs_test2");
+        assert_eq!(abbreviate("/tmp"), "/tmp");
+    }
+}
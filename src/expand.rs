@@ -0,0 +1,603 @@
+// expand.rs
+
+use crate::arith;
+use crate::ast::{Word, WordPart};
+use crate::dirs;
+use crate::glob;
+use crate::jobs;
+use crate::lexer;
+use crate::options;
+use crate::pattern;
+use crate::pipeline::{last_status, set_last_status};
+use crate::script;
+use crate::vars;
+use nix::unistd::{getuid, User};
+use std::sync::atomic::{AtomicBool, AtomicI32, Ordering};
+
+enum ParamOp {
+    Plain,
+    Length,
+    /// `${VAR-word}` / `${VAR:-word}`; the flag records the colon form,
+    /// which also treats an empty value as unset.
+    Default(bool, String),
+    Assign(bool, String),
+    Error(bool, String),
+    Alternative(bool, String),
+    /// `${VAR#pat}` / `${VAR##pat}`; the flag selects the longest match.
+    TrimPrefix(bool, String),
+    TrimSuffix(bool, String),
+}
+
+pub fn is_special_param(c: char) -> bool {
+    matches!(c, '?' | '$' | '!' | '#' | '@' | '*') || c.is_ascii_digit()
+}
+
+/// The field separators: `$IFS`, or blank and newline when it is unset.
+pub fn ifs() -> String {
+    vars::get("IFS").unwrap_or_else(|| " \t\n".to_string())
+}
+
+/// Looks up a named, positional or special parameter.
+pub fn lookup(name: &str) -> Option<String> {
+    match name {
+        "?" => Some(last_status().to_string()),
+        "$" => Some(vars::shell_pid().to_string()),
+        "!" => jobs::last_background_pid().map(|pid| pid.to_string()),
+        "#" => Some(vars::positional().len().to_string()),
+        "@" => Some(vars::positional().join(" ")),
+        // `$*` joins the parameters with the first character of IFS.
+        "*" => Some(vars::positional().join(&ifs().chars().take(1).collect::<String>())),
+        "0" => Some(vars::script_name()),
+        _ if name.bytes().all(|b| b.is_ascii_digit()) => {
+            match name.parse::<usize>().ok()?.checked_sub(1) {
+                Some(n) => vars::positional().get(n).cloned(),
+                None => Some(vars::script_name()),
+            }
+        }
+        _ => vars::get(name),
+    }
+}
+
+/// Set when an expansion fails on an unset parameter, under `set -u` or in
+/// `${VAR?word}`, until collected by `take_unset_error`.
+static UNSET_ERROR: AtomicBool = AtomicBool::new(false);
+
+/// Returns and clears whether the last expansion error was an unset
+/// parameter, on which a non-interactive shell exits 127 rather than 1.
+pub fn take_unset_error() -> bool {
+    UNSET_ERROR.swap(false, Ordering::Relaxed)
+}
+
+/// Like `lookup`, but under `set -u` an unset parameter is an error.
+pub fn value(name: &str) -> Result<String, String> {
+    match lookup(name) {
+        Some(value) => Ok(value),
+        None if options::option_enabled("nounset") => {
+            UNSET_ERROR.store(true, Ordering::Relaxed);
+            Err(format!("{}: unbound variable", name))
+        }
+        None => Ok(String::new()),
+    }
+}
+
+/// Expands the body of a `${...}` expression.
+pub fn expand_braced(body: &str) -> Result<String, String> {
+    let (name, op) = parse_braced(body)?;
+    if matches!(op, ParamOp::Plain | ParamOp::Length) {
+        value(&name)?;
+    }
+    let value = lookup(&name);
+    let unset = |colon: bool| match &value {
+        None => true,
+        Some(v) => colon && v.is_empty(),
+    };
+    match op {
+        ParamOp::Plain => Ok(value.unwrap_or_default()),
+        ParamOp::Length => Ok(value.map_or(0, |v| v.chars().count()).to_string()),
+        ParamOp::Default(colon, word) => {
+            if unset(colon) { expand_text(&word) } else { Ok(value.unwrap_or_default()) }
+        }
+        ParamOp::Assign(colon, word) => {
+            if !unset(colon) {
+                return Ok(value.unwrap_or_default());
+            }
+            if !vars::is_valid_name(&name) {
+                return Err(format!("${}: cannot assign in this way", name));
+            }
+            let word = expand_text(&word)?;
+            vars::set(&name, &word)?;
+            Ok(word)
+        }
+        ParamOp::Error(colon, word) => {
+            if !unset(colon) {
+                return Ok(value.unwrap_or_default());
+            }
+            let message = if word.is_empty() {
+                "parameter null or not set".to_string()
+            } else {
+                expand_text(&word)?
+            };
+            UNSET_ERROR.store(true, Ordering::Relaxed);
+            Err(format!("{}: {}", name, message))
+        }
+        ParamOp::Alternative(colon, word) => {
+            if unset(colon) { Ok(String::new()) } else { expand_text(&word) }
+        }
+        ParamOp::TrimPrefix(longest, pat) => {
+            let value = value.unwrap_or_default();
+            let pat = expand_text_pattern(&pat)?;
+            Ok(trim_prefix(&value, &pat, longest).to_string())
+        }
+        ParamOp::TrimSuffix(longest, pat) => {
+            let value = value.unwrap_or_default();
+            let pat = expand_text_pattern(&pat)?;
+            Ok(trim_suffix(&value, &pat, longest).to_string())
+        }
+    }
+}
+
+fn parse_braced(body: &str) -> Result<(String, ParamOp), String> {
+    let bad = || format!("${{{}}}: bad substitution", body);
+    if let Some(rest) = body.strip_prefix('#') {
+        if !rest.is_empty() {
+            return if param_name_len(rest) == rest.len() {
+                Ok((rest.to_string(), ParamOp::Length))
+            } else {
+                Err(bad())
+            };
+        }
+    }
+    let len = param_name_len(body);
+    if len == 0 {
+        return Err(bad());
+    }
+    let (name, rest) = body.split_at(len);
+    let op = if rest.is_empty() {
+        ParamOp::Plain
+    } else {
+        let (colon, rest) = match rest.strip_prefix(':') {
+            Some(rest) => (true, rest),
+            None => (false, rest),
+        };
+        let mut chars = rest.chars();
+        let word = |skip: usize| rest[skip..].to_string();
+        match (colon, chars.next(), chars.next()) {
+            (_, Some('-'), _) => ParamOp::Default(colon, word(1)),
+            (_, Some('='), _) => ParamOp::Assign(colon, word(1)),
+            (_, Some('?'), _) => ParamOp::Error(colon, word(1)),
+            (_, Some('+'), _) => ParamOp::Alternative(colon, word(1)),
+            (false, Some('#'), Some('#')) => ParamOp::TrimPrefix(true, word(2)),
+            (false, Some('#'), _) => ParamOp::TrimPrefix(false, word(1)),
+            (false, Some('%'), Some('%')) => ParamOp::TrimSuffix(true, word(2)),
+            (false, Some('%'), _) => ParamOp::TrimSuffix(false, word(1)),
+            _ => return Err(bad()),
+        }
+    };
+    Ok((name.to_string(), op))
+}
+
+/// Length in bytes of the parameter name at the start of `s`: an identifier
+/// a run of digits, or a single special character.
+fn param_name_len(s: &str) -> usize {
+    match s.chars().next() {
+        Some(c) if c.is_ascii_digit() => s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len()),
+        Some(c) if is_special_param(c) => 1,
+        Some(c) if c == '_' || c.is_ascii_alphabetic() => s
+            .find(|c: char| c != '_' && !c.is_ascii_alphanumeric())
+            .unwrap_or(s.len()),
+        _ => 0,
+    }
+}
+
+fn trim_prefix<'a>(value: &'a str, pat: &str, longest: bool) -> &'a str {
+    let mut cuts: Vec<usize> = value.char_indices().map(|(i, _)| i).chain([value.len()]).collect();
+    if longest {
+        cuts.reverse();
+    }
+    cuts.into_iter()
+        .find(|&i| pattern::matches(pat, &value[..i]))
+        .map_or(value, |i| &value[i..])
+}
+
+fn trim_suffix<'a>(value: &'a str, pat: &str, longest: bool) -> &'a str {
+    let mut cuts: Vec<usize> = value.char_indices().map(|(i, _)| i).chain([value.len()]).collect();
+    if !longest {
+        cuts.reverse();
+    }
+    cuts.into_iter()
+        .find(|&i| pattern::matches(pat, &value[i..]))
+        .map_or(value, |i| &value[..i])
+}
+
+/// Exit status of the most recent command substitution not yet collected by
+/// `take_substitution_status`, or -1.
+static SUBSTITUTION_STATUS: AtomicI32 = AtomicI32::new(-1);
+
+/// Returns and clears the status of the last command substitution. A command
+/// made only of assignments exits with it.
+pub fn take_substitution_status() -> Option<i32> {
+    Some(SUBSTITUTION_STATUS.swap(-1, Ordering::Relaxed)).filter(|&s| s >= 0)
+}
+
+/// Runs `command` in a forked subshell and returns what it wrote to standard
+/// output, minus trailing newlines. The subshell's exit status becomes `$?`.
+pub fn command_substitution(command: &str) -> Result<String, String> {
+    use nix::sys::wait::{waitpid, WaitStatus};
+    use nix::unistd::{close, dup2, fork, pipe, ForkResult};
+    use std::io::{Read, Write};
+    use std::os::unix::io::FromRawFd;
+
+    let (r, w) = pipe().map_err(|e| format!("cannot make pipe for command substitution: {}", e.desc()))?;
+    std::io::stdout().flush().ok();
+    match unsafe { fork() } {
+        Ok(ForkResult::Child) => {
+            close(r).ok();
+            dup2(w, 1).ok();
+            close(w).ok();
+            jobs::setup_subshell();
+            // The command may span lines, with here-documents among them.
+            let status = script::run_lines(command.as_bytes());
+            std::io::stdout().flush().ok();
+            unsafe { libc::_exit(status) }
+        }
+        Ok(ForkResult::Parent { child }) => {
+            close(w).ok();
+            let mut output = Vec::new();
+            unsafe { std::fs::File::from_raw_fd(r) }.read_to_end(&mut output).ok();
+            let status = loop {
+                match waitpid(child, None) {
+                    Ok(WaitStatus::Exited(_, code)) => break code,
+                    Ok(WaitStatus::Signaled(_, signal, _)) => break 128 + signal as i32,
+                    Err(nix::errno::Errno::EINTR) | Ok(_) => continue,
+                    Err(_) => break 1,
+                }
+            };
+            set_last_status(status);
+            SUBSTITUTION_STATUS.store(status, Ordering::Relaxed);
+            let mut output = String::from_utf8_lossy(&output).into_owned();
+            output.truncate(output.trim_end_matches('\n').len());
+            Ok(output)
+        }
+        Err(e) => {
+            close(r).ok();
+            close(w).ok();
+            Err(format!("fork: {}", e.desc()))
+        }
+    }
+}
+
+/// Expands words into fields. Each word goes through tilde, parameter and
+/// arithmetic expansion and command substitution, left to right; the
+/// unquoted results are split into fields on `$IFS`, and then pathname
+/// expansion is performed on the fields and quotes removed.
+pub fn expand_words(words: &[Word]) -> Result<Vec<String>, String> {
+    let mut fields = Fields::new(true);
+    for word in words {
+        fields.word(&word.parts)?;
+        fields.end_field(false);
+    }
+    Ok(glob::expand_fields(fields.done))
+}
+
+/// Expands a word to one string, without field splitting or pathname
+/// expansion, as for assignment values and `case` words.
+pub fn expand_word(word: &Word) -> Result<String, String> {
+    let mut fields = Fields::new(false);
+    fields.word(&word.parts)?;
+    Ok(fields.text)
+}
+
+/// Expands the value of a `NAME=value` assignment. Like `expand_word`, but
+/// a tilde-prefix may also follow any unquoted `:`, as in `PATH=~/bin:~/sbin`.
+pub fn expand_assignment(value: &Word) -> Result<String, String> {
+    let mut fields = Fields::new(false);
+    fields.assignment(&value.parts, true)?;
+    Ok(fields.text)
+}
+
+/// Expands a pattern word, as in a `case` arm: like `expand_word`, but
+/// quoted characters are escaped so that they match literally.
+pub fn expand_pattern(word: &Word) -> Result<String, String> {
+    let mut fields = Fields::new(false);
+    fields.word(&word.parts)?;
+    Ok(fields.pattern)
+}
+
+/// Expands source text as one word, e.g. the operand of `${VAR:-word}`.
+pub fn expand_text(text: &str) -> Result<String, String> {
+    let parts = lexer::read_text(text).map_err(|e| e.to_string())?;
+    let mut fields = Fields::new(false);
+    fields.parts(&parts, false)?;
+    Ok(fields.text)
+}
+
+/// Expands source text as a pattern, e.g. the operand of `${VAR#pattern}`,
+/// in which quoted characters match literally.
+fn expand_text_pattern(text: &str) -> Result<String, String> {
+    let parts = lexer::read_text(text).map_err(|e| e.to_string())?;
+    let mut fields = Fields::new(false);
+    fields.parts(&parts, false)?;
+    Ok(fields.pattern)
+}
+
+/// The directory a tilde-prefix `~name` stands for: `$HOME` for a bare `~`,
+/// `$PWD` for `~+`, `$OLDPWD` for `~-`, a `dirs` entry for `~N`, `~+N` or
+/// `~-N`, and otherwise the home directory of the named user.
+fn tilde_dir(name: &str) -> Option<String> {
+    let home = |user: Option<User>| user.map(|user| user.dir.to_string_lossy().into_owned());
+    match name {
+        "" => vars::get("HOME").or_else(|| home(User::from_uid(getuid()).ok().flatten())),
+        "+" => vars::get("PWD"),
+        "-" => vars::get("OLDPWD"),
+        _ if dirs::is_tilde_index(name) => dirs::tilde_entry(name),
+        _ => home(User::from_name(name).ok().flatten()),
+    }
+}
+
+/// A field produced by expansion, and the pattern to glob it with: the same
+/// text with its quoted characters escaped.
+pub struct Field {
+    pub text: String,
+    pub pattern: String,
+}
+
+/// Collects the fields of words as their parts are expanded.
+struct Fields {
+    /// Whether unquoted command substitutions are split into fields, and
+    /// `$@` into one field per parameter.
+    split: bool,
+    done: Vec<Field>,
+    text: String,
+    pattern: String,
+    /// Set by quoted parts, which keep a field even if it is empty.
+    quoted: bool,
+    /// Set when IFS whitespace has just ended a field, so that an IFS
+    /// character right after it does not end another.
+    after_blank: bool,
+}
+
+impl Fields {
+    fn new(split: bool) -> Fields {
+        Fields { split, done: Vec::new(), text: String::new(), pattern: String::new(), quoted: false, after_blank: false }
+    }
+
+    fn unquoted(&mut self, text: &str) {
+        self.text.push_str(text);
+        self.pattern.push_str(text);
+        self.after_blank = false;
+    }
+
+    fn quoted(&mut self, text: &str) {
+        self.after_blank = false;
+        self.text.push_str(text);
+        for c in text.chars() {
+            if "\\*?[]".contains(c) {
+                self.pattern.push('\\');
+            }
+            self.pattern.push(c);
+        }
+        self.quoted = true;
+    }
+
+    /// Appends the result of an unquoted expansion, split into fields at
+    /// the characters of `$IFS`. The first piece joins the text before it,
+    /// and the last stays open for the text after it. IFS whitespace only
+    /// separates fields, while any other IFS character ends one, even if
+    /// empty; whitespace around such a character belongs to it.
+    fn split(&mut self, text: &str) {
+        self.split_on(text, &ifs());
+    }
+
+    /// Like `split`, with the separators given rather than read from `$IFS`.
+    fn split_on(&mut self, text: &str, ifs: &str) {
+        if !self.split || ifs.is_empty() {
+            return self.unquoted(text);
+        }
+        for c in text.chars() {
+            if !ifs.contains(c) {
+                self.unquoted(c.encode_utf8(&mut [0; 4]));
+            } else if matches!(c, ' ' | '\t' | '\n') {
+                if !self.text.is_empty() || self.quoted {
+                    self.end_field(false);
+                    self.after_blank = true;
+                }
+            } else if self.after_blank {
+                self.after_blank = false;
+            } else {
+                self.end_field(true);
+            }
+        }
+    }
+
+    /// Expands the parts of a whole word, starting with tilde expansion of
+    /// its unquoted start. A word that looks like an assignment, such as an
+    /// argument to `export`, is treated like an assignment's value after
+    /// the `=`.
+    fn word(&mut self, parts: &[WordPart]) -> Result<(), String> {
+        let Some((WordPart::Literal(text), rest)) = parts.split_first() else {
+            return self.parts(parts, false);
+        };
+        match text.split_once('=').filter(|(name, _)| vars::is_valid_name(name)) {
+            Some((name, value)) => {
+                self.unquoted(name);
+                self.unquoted("=");
+                self.assignment_literal(value, true, rest.is_empty());
+                self.assignment(rest, false)
+            }
+            None => {
+                if !self.tilde(text, rest.is_empty()) {
+                    self.unquoted(text);
+                }
+                self.parts(rest, false)
+            }
+        }
+    }
+
+    /// Expands the parts of an assignment value. `at_start` is set if they
+    /// begin the value.
+    fn assignment(&mut self, parts: &[WordPart], mut at_start: bool) -> Result<(), String> {
+        for (i, part) in parts.iter().enumerate() {
+            match part {
+                WordPart::Literal(text) => self.assignment_literal(text, at_start, i + 1 == parts.len()),
+                _ => self.parts(std::slice::from_ref(part), false)?,
+            }
+            at_start = false;
+        }
+        Ok(())
+    }
+
+    /// Appends unquoted text of an assignment value, expanding the
+    /// tilde-prefixes that start the value (if `at_start` is set) or follow
+    /// a `:`. `last` is set if the text ends the word.
+    fn assignment_literal(&mut self, text: &str, at_start: bool, last: bool) {
+        let segments: Vec<&str> = text.split(':').collect();
+        for (i, segment) in segments.iter().enumerate() {
+            if i > 0 {
+                self.unquoted(":");
+            }
+            let whole = last || i + 1 < segments.len();
+            if !((at_start || i > 0) && self.tilde(segment, whole)) {
+                self.unquoted(segment);
+            }
+        }
+    }
+
+    /// Performs tilde expansion on `text`, the unquoted start of a word,
+    /// which is the whole word if `last` is set. The tilde-prefix runs up to
+    /// the first slash. Returns false if there is no prefix to expand.
+    fn tilde(&mut self, text: &str, last: bool) -> bool {
+        let Some(rest) = text.strip_prefix('~') else {
+            return false;
+        };
+        let end = rest.find('/');
+        if end.is_none() && !last {
+            return false;
+        }
+        let name = &rest[..end.unwrap_or(rest.len())];
+        let Some(dir) = tilde_dir(name) else {
+            return false;
+        };
+        // The directory is not split or globbed.
+        self.quoted(&dir);
+        self.unquoted(&rest[name.len()..]);
+        true
+    }
+
+    /// Ends the current field. An empty one is dropped unless it had a
+    /// quoted part or `keep` is set.
+    fn end_field(&mut self, keep: bool) {
+        if keep || self.quoted || !self.text.is_empty() {
+            let text = std::mem::take(&mut self.text);
+            let pattern = std::mem::take(&mut self.pattern);
+            self.done.push(Field { text, pattern });
+        }
+        self.pattern.clear();
+        self.quoted = false;
+    }
+
+    /// Expands `$@` (and unquoted `$*`) to one field per positional
+    /// parameter, the first joined to the text before it and the last to the
+    /// text after. Unquoted, each parameter is split further.
+    fn positional(&mut self, quoted: bool) {
+        let params = vars::positional();
+        if !self.split {
+            let joined = params.join(" ");
+            return if quoted { self.quoted(&joined) } else { self.unquoted(&joined) };
+        }
+        for (i, param) in params.iter().enumerate() {
+            if i > 0 {
+                self.end_field(quoted);
+            }
+            if quoted { self.quoted(param) } else { self.split(param) }
+        }
+    }
+
+    fn parts(&mut self, parts: &[WordPart], in_double: bool) -> Result<(), String> {
+        for part in parts {
+            let value = match part {
+                WordPart::Literal(text) if in_double => {
+                    self.quoted(text);
+                    continue;
+                }
+                WordPart::Literal(text) => {
+                    self.unquoted(text);
+                    continue;
+                }
+                WordPart::Quoted(text) => {
+                    self.quoted(text);
+                    continue;
+                }
+                WordPart::DoubleQuoted(inner) => {
+                    // `"$@"` with no parameters expands to no field at all.
+                    let vanishes = matches!(inner.as_slice(), [WordPart::Parameter(p)] if p == "@")
+                        && vars::positional().is_empty();
+                    if !vanishes {
+                        self.quoted("");
+                    }
+                    self.parts(inner, true)?;
+                    continue;
+                }
+                WordPart::Parameter(name) if name == "@" || (name == "*" && !in_double) => {
+                    self.positional(in_double);
+                    continue;
+                }
+                WordPart::Parameter(name) => value(name)?,
+                WordPart::Braced(body) => expand_braced(body)?,
+                WordPart::CommandSubst(command) => command_substitution(command)?,
+                WordPart::Arithmetic(expr) => arith::evaluate(&expand_text(expr)?)?.to_string(),
+            };
+            if in_double { self.quoted(&value) } else { self.split(&value) }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_unset_parameters_are_unset_errors() {
+        assert_eq!(expand_text("${EXPAND_TEST_UNSET?gone}"), Err("EXPAND_TEST_UNSET: gone".to_string()));
+        assert!(take_unset_error());
+        assert!(!take_unset_error());
+        assert_eq!(expand_text("$((7 / 0))"), Err("7 / 0: division by 0 (error token is \"0\")".to_string()));
+        assert!(!take_unset_error());
+        assert_eq!(expand_text("${EXPAND_TEST_UNSET!}"), Err("${EXPAND_TEST_UNSET!}: bad substitution".to_string()));
+        assert!(!take_unset_error());
+    }
+
+    #[test]
+    fn quoted_trim_patterns_match_literally() {
+        vars::set("EXPAND_TEST_TRIM", "a*b").unwrap();
+        assert_eq!(expand_braced("EXPAND_TEST_TRIM#\"a*\""), Ok("b".to_string()));
+        assert_eq!(expand_braced("EXPAND_TEST_TRIM#a\\*"), Ok("b".to_string()));
+        assert_eq!(expand_braced("EXPAND_TEST_TRIM#a*"), Ok("*b".to_string()));
+        assert_eq!(expand_braced("EXPAND_TEST_TRIM##a*"), Ok(String::new()));
+        assert_eq!(expand_braced("EXPAND_TEST_TRIM%'*b'"), Ok("a".to_string()));
+        assert_eq!(expand_braced("EXPAND_TEST_TRIM%*b"), Ok("a*".to_string()));
+    }
+
+    #[test]
+    fn all_zero_positional_names_are_dollar_zero() {
+        assert_eq!(lookup("00"), Some(vars::script_name()));
+        assert_eq!(lookup("000"), lookup("0"));
+        assert_eq!(expand_braced("00"), Ok(vars::script_name()));
+    }
+
+    #[test]
+    fn ifs_field_splitting() {
+        let split = |ifs: &str, value: &str| -> Vec<String> {
+            let mut fields = Fields::new(true);
+            fields.split_on(value, ifs);
+            fields.end_field(false);
+            fields.done.into_iter().map(|field| field.text).collect()
+        };
+        assert_eq!(split(" \t\n", "  x\ty\nz "), ["x", "y", "z"]);
+        assert_eq!(split(":", "a::b:"), ["a", "", "b"]);
+        assert_eq!(split(" :", "a : b"), ["a", "b"]);
+        assert_eq!(split(" :", " a :: b "), ["a", "", "b"]);
+        assert_eq!(split("", "a b"), ["a b"]);
+        assert_eq!(split(" \t\n", "   "), Vec::<String>::new());
+    }
+}
@@ -0,0 +1,29 @@
+// functions.rs
+
+use crate::ast::Command;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock};
+
+/// Defined shell functions by name. Each body is a compound command.
+static FUNCTIONS: OnceLock<Mutex<HashMap<String, Arc<Command>>>> = OnceLock::new();
+
+fn table() -> MutexGuard<'static, HashMap<String, Arc<Command>>> {
+    FUNCTIONS.get_or_init(|| Mutex::new(HashMap::new())).lock().unwrap()
+}
+
+pub fn define(name: &str, body: Arc<Command>) {
+    table().insert(name.to_string(), body);
+}
+
+pub fn get(name: &str) -> Option<Arc<Command>> {
+    table().get(name).cloned()
+}
+
+pub fn is_function(name: &str) -> bool {
+    table().contains_key(name)
+}
+
+/// Removes a function. Returns false if there was none by that name.
+pub fn unset(name: &str) -> bool {
+    table().remove(name).is_some()
+}
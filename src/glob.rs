@@ -0,0 +1,164 @@
+// glob.rs
+
+use crate::expand::Field;
+use crate::options::shopt_enabled;
+use crate::pattern;
+use std::path::Path;
+
+/// Performs pathname expansion on fields with unquoted pattern characters.
+/// A pattern that matches nothing is kept as-is, or removed when `nullglob`
+/// is set.
+pub fn expand_fields(fields: Vec<Field>) -> Vec<String> {
+    let mut out = Vec::with_capacity(fields.len());
+    for field in fields {
+        if !pattern::has_meta(&field.pattern) {
+            out.push(field.text);
+            continue;
+        }
+        let matches = expand(&field.pattern);
+        if matches.is_empty() {
+            if !shopt_enabled("nullglob") {
+                out.push(field.text);
+            }
+        } else {
+            out.extend(matches);
+        }
+    }
+    out
+}
+
+/// Expands a pathname pattern into the sorted list of matching paths.
+pub fn expand(pat: &str) -> Vec<String> {
+    let (mut paths, rest) = match pat.strip_prefix('/') {
+        Some(rest) => (vec!["/".to_string()], rest),
+        None => (vec![String::new()], pat),
+    };
+    let dotglob = shopt_enabled("dotglob");
+    let components: Vec<&str> = rest.split('/').collect();
+    for (i, comp) in components.iter().enumerate() {
+        let last = i == components.len() - 1;
+        let mut next = Vec::new();
+        for base in &paths {
+            if comp.is_empty() {
+                // A trailing or doubled slash only keeps directories.
+                if is_dir(base) {
+                    next.push(format!("{}/", base.trim_end_matches('/')));
+                }
+            } else if *comp == "**" && shopt_enabled("globstar") {
+                if !last {
+                    next.push(base.clone());
+                }
+                walk(base, dotglob, !last, &mut next);
+            } else if pattern::has_meta(comp) {
+                let hidden_ok = dotglob || comp.starts_with('.');
+                for name in list_dir(base) {
+                    if (hidden_ok || !name.starts_with('.')) && pattern::matches(comp, &name) {
+                        let path = join(base, &name);
+                        if last || is_dir(&path) {
+                            next.push(path);
+                        }
+                    }
+                }
+            } else {
+                next.push(join(base, &unescape(comp)));
+            }
+        }
+        paths = next;
+    }
+    let mut paths: Vec<String> = paths.into_iter()
+        .filter(|p| !p.is_empty() && Path::new(p).symlink_metadata().is_ok())
+        .collect();
+    paths.sort();
+    paths.dedup();
+    paths
+}
+
+/// Collects everything below `base` for `**`, or only directories when
+/// `dirs_only` is set.
+fn walk(base: &str, dotglob: bool, dirs_only: bool, out: &mut Vec<String>) {
+    for name in list_dir(base) {
+        if !dotglob && name.starts_with('.') {
+            continue;
+        }
+        let path = join(base, &name);
+        let is_dir = Path::new(&path).symlink_metadata().is_ok_and(|m| m.is_dir());
+        if is_dir || !dirs_only {
+            out.push(path.clone());
+        }
+        if is_dir {
+            walk(&path, dotglob, dirs_only, out);
+        }
+    }
+}
+
+fn list_dir(base: &str) -> Vec<String> {
+    let dir = if base.is_empty() { "." } else { base };
+    match std::fs::read_dir(dir) {
+        Ok(entries) => entries
+            .flatten()
+            .filter_map(|e| e.file_name().into_string().ok())
+            .collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+fn is_dir(path: &str) -> bool {
+    Path::new(if path.is_empty() { "." } else { path }).is_dir()
+}
+
+fn join(base: &str, name: &str) -> String {
+    if base.is_empty() {
+        name.to_string()
+    } else if base.ends_with('/') {
+        format!("{}{}", base, name)
+    } else {
+        format!("{}/{}", base, name)
+    }
+}
+
+fn unescape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => out.extend(chars.next()),
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expands_patterns_in_each_component() {
+        let dir = std::env::temp_dir().join(format!("glob_test_{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("sub")).unwrap();
+        for name in ["a.rs", "b.rs", ".h.rs", "c.txt", "sub/d.rs"] {
+            std::fs::write(dir.join(name), "").unwrap();
+        }
+        let base = dir.to_str().unwrap().to_string();
+        let glob = |pat: &str| -> Vec<String> {
+            expand(&format!("{}/{}", base, pat)).into_iter()
+                .map(|p| p[base.len() + 1..].to_string())
+                .collect()
+        };
+        assert_eq!(glob("*.rs"), ["a.rs", "b.rs"]);
+        assert_eq!(glob(".*.rs"), [".h.rs"]);
+        assert_eq!(glob("[!a].*"), ["b.rs", "c.txt"]);
+        assert_eq!(glob("*/"), ["sub/"]);
+        assert_eq!(glob("*/?.rs"), ["sub/d.rs"]);
+        assert_eq!(glob("*.md"), Vec::<String>::new());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn escaped_or_unclosed_meta_is_literal() {
+        assert!(pattern::has_meta("a*"));
+        assert!(pattern::has_meta("[ab]"));
+        assert!(!pattern::has_meta("a\\*"));
+        assert!(!pattern::has_meta("[ab"));
+    }
+}
@@ -0,0 +1,627 @@
+// jobs.rs
+
+use std::io::IsTerminal;
+use std::os::unix::io::RawFd;
+use std::sync::atomic::{AtomicBool, AtomicI32, Ordering};
+use std::sync::{Mutex, MutexGuard, OnceLock};
+use nix::errno::Errno;
+use nix::fcntl::{fcntl, FcntlArg};
+use nix::sys::signal::{killpg, raise, Signal};
+use nix::sys::wait::{waitpid, WaitPidFlag, WaitStatus};
+use nix::unistd::{getpgrp, getpid, setpgid, tcsetpgrp, Pid};
+use crate::signals;
+use crate::util::writeln_ignore_broken_pipe;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum ProcState {
+    Running,
+    Stopped,
+    Exited(i32),
+    Signaled(i32),
+}
+
+impl ProcState {
+    fn from_wait(status: WaitStatus) -> Option<ProcState> {
+        match status {
+            WaitStatus::Exited(_, code) => Some(ProcState::Exited(code)),
+            WaitStatus::Signaled(_, sig, _) => Some(ProcState::Signaled(sig as i32)),
+            WaitStatus::Stopped(..) => Some(ProcState::Stopped),
+            WaitStatus::Continued(_) => Some(ProcState::Running),
+            _ => None,
+        }
+    }
+
+    fn finished(self) -> bool {
+        matches!(self, ProcState::Exited(_) | ProcState::Signaled(_))
+    }
+
+    /// The shell exit status: the exit code, or 128+N for death by signal N.
+    fn status(self) -> i32 {
+        match self {
+            ProcState::Exited(code) => code,
+            ProcState::Signaled(sig) => 128 + sig,
+            _ => 0,
+        }
+    }
+}
+
+struct Process {
+    pid: Pid,
+    state: ProcState,
+}
+
+/// A pipeline started by the shell, identified by its process group.
+pub struct Job {
+    id: usize,
+    pgid: Pid,
+    procs: Vec<Process>,
+    command: String,
+    /// Set when the job finished or stopped in the background and the user
+    /// has not been told yet.
+    changed: bool,
+}
+
+impl Job {
+    pub fn new(pgid: Pid, pids: &[Pid], command: &str) -> Self {
+        Self {
+            id: 0,
+            pgid,
+            procs: pids.iter().map(|&pid| Process { pid, state: ProcState::Running }).collect(),
+            command: command.to_string(),
+            changed: false,
+        }
+    }
+
+    fn finished(&self) -> bool {
+        self.procs.iter().all(|p| p.state.finished())
+    }
+
+    fn stopped(&self) -> bool {
+        !self.finished() && self.procs.iter().all(|p| p.state != ProcState::Running)
+    }
+
+    fn status(&self) -> i32 {
+        self.procs.last().map_or(0, |p| p.state.status())
+    }
+
+    fn describe(&self) -> String {
+        if self.stopped() {
+            return "Stopped".to_string();
+        }
+        if !self.finished() {
+            return "Running".to_string();
+        }
+        match self.procs.last().map(|p| p.state) {
+            Some(ProcState::Exited(0)) | None => "Done".to_string(),
+            Some(ProcState::Signaled(sig)) => signal_description(sig),
+            Some(state) => format!("Exit {}", state.status()),
+        }
+    }
+
+    fn continue_all(&mut self) {
+        for proc in self.procs.iter_mut().filter(|p| !p.state.finished()) {
+            proc.state = ProcState::Running;
+        }
+        let _ = killpg(self.pgid, Signal::SIGCONT);
+    }
+}
+
+#[derive(Default)]
+struct JobTable {
+    jobs: Vec<Job>,
+    /// Job ids from least to most recently used: the last one is the current
+    /// job (`%+`), the one before it the previous job (`%-`).
+    recent: Vec<usize>,
+    /// Processes of disowned jobs, still reaped so they do not linger as
+    /// zombies.
+    disowned: Vec<Pid>,
+}
+
+impl JobTable {
+    fn insert(&mut self, mut job: Job) -> usize {
+        if job.id == 0 {
+            job.id = self.jobs.iter().map(|j| j.id).max().unwrap_or(0) + 1;
+        }
+        let id = job.id;
+        let pos = self.jobs.partition_point(|j| j.id < id);
+        self.jobs.insert(pos, job);
+        self.touch(id);
+        id
+    }
+
+    fn touch(&mut self, id: usize) {
+        self.recent.retain(|&r| r != id);
+        self.recent.push(id);
+    }
+
+    fn remove(&mut self, index: usize) -> Job {
+        let job = self.jobs.remove(index);
+        self.recent.retain(|&r| r != job.id);
+        job
+    }
+
+    fn marker(&self, id: usize) -> char {
+        let mut recent = self.recent.iter().rev();
+        if recent.next() == Some(&id) {
+            '+'
+        } else if recent.next() == Some(&id) {
+            '-'
+        } else {
+            ' '
+        }
+    }
+
+    fn format(&self, job: &Job, long: bool) -> String {
+        let mut command = job.command.clone();
+        if !job.finished() && !job.stopped() {
+            command.push_str(" &");
+        }
+        let pid = if long { format!(" {}", job.pgid) } else { String::new() };
+        format!("[{}]{}{}  {:<24}{}", job.id, self.marker(job.id), pid, job.describe(), command)
+    }
+
+    /// Resolves a job spec (`%n`, `%+`, `%-`, `%prefix`, `%?text`, or a bare
+    /// job number) to an index into `jobs`. `None` means the current job.
+    fn resolve(&self, spec: Option<&str>) -> Result<usize, String> {
+        let id = match spec {
+            None | Some("%") | Some("%%") | Some("%+") => self.recent.last().copied(),
+            Some("%-") => self.recent.iter().rev().nth(1).copied(),
+            Some(spec) => {
+                let body = spec.strip_prefix('%').unwrap_or(spec);
+                let found = if let Ok(n) = body.parse::<usize>() {
+                    self.jobs.iter().find(|j| j.id == n)
+                } else if let Some(text) = body.strip_prefix('?') {
+                    self.jobs.iter().find(|j| j.command.contains(text))
+                } else {
+                    self.jobs.iter().find(|j| j.command.starts_with(body))
+                };
+                found.map(|j| j.id)
+            }
+        };
+        id.and_then(|id| self.jobs.iter().position(|j| j.id == id))
+            .ok_or_else(|| format!("{}: no such job", spec.unwrap_or("current")))
+    }
+
+    /// Removes finished jobs, returning the notification lines for those the
+    /// user has not seen yet.
+    fn sweep(&mut self) -> Vec<String> {
+        let mut lines = Vec::new();
+        for job in &self.jobs {
+            if job.changed {
+                lines.push(self.format(job, false));
+            }
+        }
+        for job in &mut self.jobs {
+            job.changed = false;
+        }
+        let finished: Vec<usize> = self.jobs.iter().filter(|j| j.finished()).map(|j| j.id).collect();
+        self.jobs.retain(|j| !j.finished());
+        self.recent.retain(|id| !finished.contains(id));
+        lines
+    }
+}
+
+static JOBS: OnceLock<Mutex<JobTable>> = OnceLock::new();
+static JOB_CONTROL: AtomicBool = AtomicBool::new(false);
+static TERMINAL: AtomicI32 = AtomicI32::new(-1);
+static LAST_BACKGROUND_PID: AtomicI32 = AtomicI32::new(0);
+
+fn table() -> MutexGuard<'static, JobTable> {
+    JOBS.get_or_init(|| Mutex::new(JobTable::default())).lock().unwrap()
+}
+
+fn terminal() -> RawFd {
+    TERMINAL.load(Ordering::Relaxed)
+}
+
+/// Enables job control when the shell reads from a terminal: the shell
+/// moves into its own process group, takes the terminal and ignores the
+/// job-control stop signals.
+pub fn init_job_control() {
+    if !std::io::stdin().is_terminal() {
+        return;
+    }
+    signals::init_interactive_signals();
+    let shell = getpid();
+    let _ = setpgid(shell, shell);
+    let Ok(fd) = fcntl(0, FcntlArg::F_DUPFD_CLOEXEC(10)) else { return };
+    TERMINAL.store(fd, Ordering::Relaxed);
+    let _ = tcsetpgrp(fd, getpgrp());
+    JOB_CONTROL.store(true, Ordering::Relaxed);
+}
+
+pub fn job_control_enabled() -> bool {
+    JOB_CONTROL.load(Ordering::Relaxed)
+}
+
+/// `$!`: the pid of the last process of the most recent background job.
+pub fn last_background_pid() -> Option<i32> {
+    Some(LAST_BACKGROUND_PID.load(Ordering::Relaxed)).filter(|&pid| pid != 0)
+}
+
+/// Prepares a freshly forked child: joins the job's process group (a new
+/// one led by the child if `pgid` is `None`), takes the terminal if the job
+/// runs in the foreground, and restores default signal handling. Children
+/// never do job control themselves.
+pub fn setup_child(pgid: Option<Pid>, foreground: bool) {
+    if JOB_CONTROL.swap(false, Ordering::Relaxed) {
+        let pgid = pgid.unwrap_or_else(getpid);
+        let _ = setpgid(Pid::from_raw(0), pgid);
+        if foreground {
+            let _ = tcsetpgrp(terminal(), pgid);
+        }
+    }
+    signals::restore_defaults();
+    // A Ctrl-C that reached the child before that was caught by the
+    // shell's handler; act on it now that SIGINT is back to its default.
+    if signals::interrupted() {
+        let _ = raise(Signal::SIGINT);
+    }
+}
+
+/// Prepares a forked subshell that stays in the shell's own process group,
+/// such as the child running a command substitution.
+pub fn setup_subshell() {
+    JOB_CONTROL.store(false, Ordering::Relaxed);
+    signals::restore_defaults();
+}
+
+/// Parent-side counterpart of `setup_child`. Both sides set the process
+/// group so that neither can run ahead of the other. Returns the job's
+/// process group.
+pub fn track_child(child: Pid, pgid: Option<Pid>, foreground: bool) -> Pid {
+    let pgid = pgid.unwrap_or(child);
+    if job_control_enabled() {
+        let _ = setpgid(child, pgid);
+        if foreground {
+            let _ = tcsetpgrp(terminal(), pgid);
+        }
+    }
+    pgid
+}
+
+fn take_terminal() {
+    if job_control_enabled() {
+        let _ = tcsetpgrp(terminal(), getpgrp());
+    }
+}
+
+fn wait_retrying(pid: Pid, flags: Option<WaitPidFlag>) -> nix::Result<WaitStatus> {
+    loop {
+        match waitpid(pid, flags) {
+            Err(Errno::EINTR) => continue,
+            result => return result,
+        }
+    }
+}
+
+/// Waits for a foreground job until it finishes or stops. A stopped job is
+/// moved to the job table. Returns the job's exit status.
+pub fn wait_job(mut job: Job) -> i32 {
+    let flags = job_control_enabled().then_some(WaitPidFlag::WUNTRACED);
+    if job_control_enabled() {
+        let _ = tcsetpgrp(terminal(), job.pgid);
+    }
+    for proc in job.procs.iter_mut().filter(|p| !p.state.finished()) {
+        proc.state = match wait_retrying(proc.pid, flags) {
+            Ok(status) => ProcState::from_wait(status).unwrap_or(proc.state),
+            Err(_) => ProcState::Exited(1),
+        };
+        if proc.state == ProcState::Stopped {
+            break;
+        }
+    }
+    if !job.procs.iter().any(|p| p.state == ProcState::Stopped) {
+        take_terminal();
+        if job.procs.iter().any(|p| p.state == ProcState::Signaled(Signal::SIGINT as i32)) {
+            // The shell's own handler already ended the line if the signal
+            // reached it first.
+            if job_control_enabled() && !signals::interrupted() {
+                println!();
+            }
+            signals::set_interrupted();
+        }
+        return job.status();
+    }
+    // Ctrl-Z stops the whole process group; pick up the other members.
+    let flags = WaitPidFlag::WUNTRACED | WaitPidFlag::WNOHANG;
+    for proc in job.procs.iter_mut().filter(|p| p.state == ProcState::Running) {
+        if let Ok(status) = waitpid(proc.pid, Some(flags)) {
+            proc.state = ProcState::from_wait(status).unwrap_or(proc.state);
+        }
+    }
+    take_terminal();
+    let mut table = table();
+    let id = table.insert(job);
+    let job = table.jobs.iter().find(|j| j.id == id).unwrap();
+    println!();
+    let _ = writeln_ignore_broken_pipe(std::io::stdout(), table.format(job, false));
+    128 + Signal::SIGTSTP as i32
+}
+
+/// Registers a job started with `&` and, in an interactive shell, reports
+/// its number and pid.
+pub fn add_background(job: Job) {
+    let last_pid = job.procs.last().map_or(job.pgid, |p| p.pid);
+    LAST_BACKGROUND_PID.store(last_pid.as_raw(), Ordering::Relaxed);
+    let id = table().insert(job);
+    if job_control_enabled() {
+        let _ = writeln_ignore_broken_pipe(std::io::stdout(), format!("[{}] {}", id, last_pid));
+    }
+}
+
+/// Collects status changes of background jobs without blocking.
+fn reap() {
+    let flags = WaitPidFlag::WNOHANG | WaitPidFlag::WUNTRACED | WaitPidFlag::WCONTINUED;
+    let mut table = table();
+    for job in &mut table.jobs {
+        let was_stopped = job.stopped();
+        for proc in job.procs.iter_mut().filter(|p| !p.state.finished()) {
+            match waitpid(proc.pid, Some(flags)) {
+                Ok(status) => proc.state = ProcState::from_wait(status).unwrap_or(proc.state),
+                Err(_) => proc.state = ProcState::Exited(0),
+            }
+        }
+        if job.finished() || job.stopped() && !was_stopped {
+            job.changed = true;
+        }
+    }
+    table.disowned.retain(|&pid| matches!(waitpid(pid, Some(WaitPidFlag::WNOHANG)), Ok(WaitStatus::StillAlive)));
+}
+
+/// Reports background jobs that finished or stopped since the last prompt
+/// and forgets the finished ones.
+pub fn notify() {
+    reap();
+    let lines = table().sweep();
+    if job_control_enabled() {
+        for line in lines {
+            let _ = writeln_ignore_broken_pipe(std::io::stdout(), line);
+        }
+    }
+}
+
+fn signal_description(sig: i32) -> String {
+    let description = match Signal::try_from(sig) {
+        Ok(Signal::SIGHUP) => "Hangup",
+        Ok(Signal::SIGINT) => "Interrupt",
+        Ok(Signal::SIGQUIT) => "Quit",
+        Ok(Signal::SIGABRT) => "Aborted",
+        Ok(Signal::SIGKILL) => "Killed",
+        Ok(Signal::SIGSEGV) => "Segmentation fault",
+        Ok(Signal::SIGPIPE) => "Broken pipe",
+        Ok(Signal::SIGTERM) => "Terminated",
+        _ => return format!("Signal {}", sig),
+    };
+    description.to_string()
+}
+
+pub fn builtin_jobs(args: &[String]) -> i32 {
+    reap();
+    let long = args.iter().any(|a| a == "-l");
+    let pids_only = args.iter().any(|a| a == "-p");
+    let specs: Vec<&str> = args.iter().map(String::as_str).filter(|a| !a.starts_with('-')).collect();
+    let mut table = table();
+    let indices: Vec<usize> = if specs.is_empty() {
+        (0..table.jobs.len()).collect()
+    } else {
+        let mut indices = Vec::new();
+        for spec in specs {
+            match table.resolve(Some(spec)) {
+                Ok(index) => indices.push(index),
+                Err(e) => {
+                    eprintln!("jobs: {}", e);
+                    return 1;
+                }
+            }
+        }
+        indices
+    };
+    for index in indices {
+        let job = &table.jobs[index];
+        let line = if pids_only { job.pgid.to_string() } else { table.format(job, long) };
+        let _ = writeln_ignore_broken_pipe(std::io::stdout(), line);
+    }
+    // Finished jobs have now been reported.
+    for job in &mut table.jobs {
+        job.changed = false;
+    }
+    table.sweep();
+    0
+}
+
+pub fn builtin_fg(args: &[String]) -> i32 {
+    if !job_control_enabled() {
+        eprintln!("fg: no job control");
+        return 1;
+    }
+    reap();
+    let mut job = {
+        let mut table = table();
+        match table.resolve(args.first().map(String::as_str)) {
+            Ok(index) if table.jobs[index].finished() => {
+                eprintln!("fg: job has terminated");
+                table.remove(index);
+                return 1;
+            }
+            Ok(index) => table.remove(index),
+            Err(e) => {
+                eprintln!("fg: {}", e);
+                return 1;
+            }
+        }
+    };
+    let _ = writeln_ignore_broken_pipe(std::io::stdout(), &job.command);
+    let _ = tcsetpgrp(terminal(), job.pgid);
+    job.continue_all();
+    wait_job(job)
+}
+
+pub fn builtin_bg(args: &[String]) -> i32 {
+    if !job_control_enabled() {
+        eprintln!("bg: no job control");
+        return 1;
+    }
+    reap();
+    let mut table = table();
+    let index = match table.resolve(args.first().map(String::as_str)) {
+        Ok(index) => index,
+        Err(e) => {
+            eprintln!("bg: {}", e);
+            return 1;
+        }
+    };
+    if table.jobs[index].finished() {
+        eprintln!("bg: job has terminated");
+        return 1;
+    }
+    table.jobs[index].continue_all();
+    let id = table.jobs[index].id;
+    table.touch(id);
+    let line = format!("[{}]{} {} &", id, table.marker(id), table.jobs[index].command);
+    let _ = writeln_ignore_broken_pipe(std::io::stdout(), line);
+    0
+}
+
+/// Blocks until every process of `job` has exited and returns its status.
+fn wait_for_exit(mut job: Job) -> i32 {
+    for proc in job.procs.iter_mut().filter(|p| !p.state.finished()) {
+        proc.state = match wait_retrying(proc.pid, None) {
+            Ok(status) => ProcState::from_wait(status).unwrap_or(ProcState::Exited(0)),
+            Err(_) => ProcState::Exited(127),
+        };
+    }
+    job.status()
+}
+
+pub fn builtin_wait(args: &[String]) -> i32 {
+    if args.is_empty() {
+        let jobs = std::mem::take(&mut table().jobs);
+        table().recent.clear();
+        for job in jobs {
+            wait_for_exit(job);
+        }
+        return 0;
+    }
+    let mut status = 0;
+    for arg in args {
+        if arg.starts_with('%') {
+            let job = {
+                let mut table = table();
+                match table.resolve(Some(arg)) {
+                    Ok(index) => table.remove(index),
+                    Err(e) => {
+                        eprintln!("wait: {}", e);
+                        status = 127;
+                        continue;
+                    }
+                }
+            };
+            status = wait_for_exit(job);
+            continue;
+        }
+        let Ok(pid) = arg.parse::<i32>() else {
+            eprintln!("wait: `{}': not a pid or valid job spec", arg);
+            status = 2;
+            continue;
+        };
+        let pid = Pid::from_raw(pid);
+        let known = table().jobs.iter()
+            .flat_map(|j| &j.procs)
+            .find(|p| p.pid == pid)
+            .map(|p| p.state);
+        if let Some(state) = known.filter(|s| s.finished()) {
+            status = state.status();
+            continue;
+        }
+        let state = match wait_retrying(pid, None) {
+            Ok(status) => ProcState::from_wait(status).unwrap_or(ProcState::Exited(0)),
+            Err(_) => {
+                eprintln!("wait: pid {} is not a child of this shell", pid);
+                status = 127;
+                continue;
+            }
+        };
+        for job in &mut table().jobs {
+            for proc in job.procs.iter_mut().filter(|p| p.pid == pid) {
+                proc.state = state;
+            }
+        }
+        status = state.status();
+    }
+    status
+}
+
+pub fn builtin_disown(args: &[String]) -> i32 {
+    let mut table = table();
+    let indices: Vec<usize> = if args.iter().any(|a| a == "-a") {
+        (0..table.jobs.len()).collect()
+    } else {
+        let specs: Vec<Option<&str>> = if args.is_empty() {
+            vec![None]
+        } else {
+            args.iter().map(|a| Some(a.as_str())).collect()
+        };
+        let mut indices = Vec::new();
+        for spec in specs {
+            match table.resolve(spec) {
+                Ok(index) => indices.push(index),
+                Err(e) => {
+                    eprintln!("disown: {}", e);
+                    return 1;
+                }
+            }
+        }
+        indices
+    };
+    let mut indices = indices;
+    indices.sort_unstable();
+    indices.dedup();
+    for index in indices.into_iter().rev() {
+        let job = table.remove(index);
+        let pids = job.procs.iter().filter(|p| !p.state.finished()).map(|p| p.pid);
+        table.disowned.extend(pids);
+    }
+    0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table_of(commands: &[&str]) -> JobTable {
+        let mut table = JobTable::default();
+        for (i, command) in commands.iter().enumerate() {
+            let pid = Pid::from_raw(900_000 + i as i32);
+            table.insert(Job::new(pid, &[pid], command));
+        }
+        table
+    }
+
+    #[test]
+    fn job_specs() {
+        let table = table_of(&["sleep 10", "grep foo", "sleep 20"]);
+        let id = |spec: Option<&str>| table.resolve(spec).map(|i| table.jobs[i].id);
+        assert_eq!(id(None), Ok(3));
+        assert_eq!(id(Some("%-")), Ok(2));
+        assert_eq!(id(Some("%1")), Ok(1));
+        assert_eq!(id(Some("2")), Ok(2));
+        assert_eq!(id(Some("%sleep")), Ok(1));
+        assert_eq!(id(Some("%?foo")), Ok(2));
+        assert_eq!(id(Some("%9")), Err("%9: no such job".to_string()));
+    }
+
+    #[test]
+    fn listing_and_sweeping_finished_jobs() {
+        let mut table = table_of(&["sleep 10", "false"]);
+        assert_eq!(table.format(&table.jobs[0], false), "[1]-  Running                 sleep 10 &");
+        let job = &mut table.jobs[1];
+        job.procs[0].state = ProcState::Exited(1);
+        job.changed = true;
+        assert_eq!(table.sweep(), ["[2]+  Exit 1                  false"]);
+        assert_eq!(table.jobs.len(), 1);
+        assert_eq!(table.marker(1), '+');
+        // A new job is numbered one past the highest in use.
+        assert_eq!(table.insert(Job::new(Pid::from_raw(1), &[], "true")), 2);
+    }
+}
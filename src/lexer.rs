@@ -0,0 +1,262 @@
+// lexer.rs
+
+use crate::ast::{Span, Word, WordPart};
+use crate::expand;
+use crate::parser::{arithmetic_body, command_subst_end, skip_to_close, ParseError, ParseErrorKind};
+
+/// Characters that end an unquoted word.
+fn is_metachar(c: char) -> bool {
+    matches!(c, ' ' | '\t' | '\n' | ';' | '&' | '|' | '(' | ')' | '<' | '>')
+}
+
+/// Reads the word that starts at byte `start` of `text`, up to the first
+/// unquoted metacharacter. The word is empty if `start` is at one.
+pub fn read_word(text: &str, start: usize) -> Result<Word, ParseError> {
+    let mut lexer = Lexer { text, pos: start };
+    let parts = lexer.parts(true)?;
+    let span = Span { start, end: lexer.pos };
+    Ok(Word { parts, text: text[start..lexer.pos].to_string(), span })
+}
+
+/// Reads all of `text` as the parts of one word, in which blanks and
+/// operators are ordinary characters. Used for text expanded as a whole,
+/// such as the operand of `${name:-word}`.
+pub fn read_text(text: &str) -> Result<Vec<WordPart>, ParseError> {
+    Lexer { text, pos: 0 }.parts(false)
+}
+
+struct Lexer<'a> {
+    text: &'a str,
+    pos: usize,
+}
+
+impl<'a> Lexer<'a> {
+    fn rest(&self) -> &'a str {
+        &self.text[self.pos..]
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.rest().chars().next()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += c.len_utf8();
+        Some(c)
+    }
+
+    /// The error for a quote or substitution opened at `start` that is
+    /// still looking for `close` at the end of the text.
+    fn unclosed(&self, close: char, start: usize) -> ParseError {
+        ParseError::new(ParseErrorKind::Unclosed(close), self.text, start)
+    }
+
+    /// Reads unquoted text and the quoted parts and expansions within it.
+    /// With `word` set, stops at the first metacharacter.
+    fn parts(&mut self, word: bool) -> Result<Vec<WordPart>, ParseError> {
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        while let Some(c) = self.peek() {
+            if word && is_metachar(c) {
+                break;
+            }
+            let start = self.pos;
+            self.bump();
+            let part = match c {
+                '\\' => match self.bump() {
+                    // A backslash-newline joins two lines.
+                    Some('\n') => continue,
+                    Some(next) => WordPart::Quoted(next.to_string()),
+                    None if word => return Err(ParseError::new(ParseErrorKind::Incomplete, self.text, start)),
+                    None => {
+                        literal.push('\\');
+                        continue;
+                    }
+                },
+                '\'' => {
+                    let len = self.rest().find('\'').ok_or_else(|| self.unclosed('\'', start))?;
+                    let quoted = self.rest()[..len].to_string();
+                    self.pos += len + 1;
+                    WordPart::Quoted(quoted)
+                }
+                '"' => WordPart::DoubleQuoted(self.double_quoted(start)?),
+                '`' => WordPart::CommandSubst(self.backquoted(start, false)?),
+                '$' => match self.dollar(start)? {
+                    Some(part) => part,
+                    None => {
+                        literal.push('$');
+                        continue;
+                    }
+                },
+                c => {
+                    literal.push(c);
+                    continue;
+                }
+            };
+            if !literal.is_empty() {
+                parts.push(WordPart::Literal(std::mem::take(&mut literal)));
+            }
+            parts.push(part);
+        }
+        if !literal.is_empty() {
+            parts.push(WordPart::Literal(literal));
+        }
+        Ok(parts)
+    }
+
+    /// Reads the inside of `"..."`, whose opening quote was at `start`.
+    /// A backslash only escapes `$`, `` ` ``, `"`, `\` and newline here.
+    fn double_quoted(&mut self, start: usize) -> Result<Vec<WordPart>, ParseError> {
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        loop {
+            let part_start = self.pos;
+            let part = match self.bump() {
+                None => return Err(self.unclosed('"', start)),
+                Some('"') => break,
+                Some('\\') => {
+                    match self.peek() {
+                        Some('\n') => { self.bump(); }
+                        Some(next @ ('$' | '`' | '"' | '\\')) => {
+                            self.bump();
+                            literal.push(next);
+                        }
+                        _ => literal.push('\\'),
+                    }
+                    continue;
+                }
+                Some('`') => WordPart::CommandSubst(self.backquoted(part_start, true)?),
+                Some('$') => match self.dollar(part_start)? {
+                    Some(part) => part,
+                    None => {
+                        literal.push('$');
+                        continue;
+                    }
+                },
+                Some(c) => {
+                    literal.push(c);
+                    continue;
+                }
+            };
+            if !literal.is_empty() {
+                parts.push(WordPart::Literal(std::mem::take(&mut literal)));
+            }
+            parts.push(part);
+        }
+        if !literal.is_empty() {
+            parts.push(WordPart::Literal(literal));
+        }
+        Ok(parts)
+    }
+
+    /// Reads the command of a `` `...` `` substitution. Inside backquotes a
+    /// backslash only escapes `$`, `` ` `` and `\` (and `"` within double
+    /// quotes).
+    fn backquoted(&mut self, start: usize, in_double: bool) -> Result<String, ParseError> {
+        let mut body = String::new();
+        while let Some(c) = self.bump() {
+            match c {
+                '`' => return Ok(body),
+                '\\' => match self.bump() {
+                    Some(next @ ('$' | '`' | '\\')) => body.push(next),
+                    Some('"') if in_double => body.push('"'),
+                    Some(next) => {
+                        body.push('\\');
+                        body.push(next);
+                    }
+                    None => body.push('\\'),
+                },
+                c => body.push(c),
+            }
+        }
+        Err(self.unclosed('`', start))
+    }
+
+    /// Reads the expansion after a `$` at `start`. Returns `None` if the `$`
+    /// does not start one and is literal.
+    fn dollar(&mut self, start: usize) -> Result<Option<WordPart>, ParseError> {
+        let rest = self.rest();
+        let part = match self.peek() {
+            Some('{') => {
+                let end = skip_to_close(&mut rest[1..].char_indices().peekable(), '{', '}')
+                    .ok_or_else(|| self.unclosed('}', start))?;
+                self.pos += end + 2;
+                WordPart::Braced(rest[1..end + 1].to_string())
+            }
+            Some('(') => {
+                // `$((expr))` is arithmetic when the inner parentheses
+                // balance; anything else is a command substitution.
+                let end = skip_to_close(&mut rest[1..].char_indices().peekable(), '(', ')');
+                let arithmetic = end.and_then(|end| Some((end, arithmetic_body(&rest[1..end + 1])?)));
+                if let Some((end, expr)) = arithmetic {
+                    self.pos += end + 2;
+                    WordPart::Arithmetic(expr.to_string())
+                } else {
+                    let end = command_subst_end(self.text, start)?;
+                    self.pos = end + 1;
+                    WordPart::CommandSubst(self.text[start + 2..end].to_string())
+                }
+            }
+            Some(c) if expand::is_special_param(c) => {
+                self.bump();
+                WordPart::Parameter(c.to_string())
+            }
+            Some(c) if c == '_' || c.is_ascii_alphabetic() => {
+                let len = rest.find(|c: char| c != '_' && !c.is_ascii_alphanumeric()).unwrap_or(rest.len());
+                self.pos += len;
+                WordPart::Parameter(rest[..len].to_string())
+            }
+            _ => return Ok(None),
+        };
+        Ok(Some(part))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn word_ends_at_metachar() {
+        let word = read_word("echo hi;", 5).unwrap();
+        assert_eq!(word.text, "hi");
+        assert_eq!(word.span, Span { start: 5, end: 7 });
+        assert!(read_word("|x", 0).unwrap().parts.is_empty());
+    }
+
+    #[test]
+    fn quoting() {
+        let word = read_word("a'b c'\\ d\"$x\" x", 0).unwrap();
+        assert_eq!(word.text, "a'b c'\\ d\"$x\"");
+        assert!(matches!(&word.parts[..], [
+            WordPart::Literal(a), WordPart::Quoted(bc), WordPart::Quoted(space), WordPart::Literal(d),
+            WordPart::DoubleQuoted(inner),
+        ] if a == "a" && bc == "b c" && space == " " && d == "d"
+            && matches!(&inner[..], [WordPart::Parameter(x)] if x == "x")));
+    }
+
+    #[test]
+    fn substitutions() {
+        let parts = read_word("$((1+(2)))$(echo)${v}`date`$?", 0).unwrap().parts;
+        assert!(matches!(&parts[..], [
+            WordPart::Arithmetic(a), WordPart::CommandSubst(c), WordPart::Braced(b),
+            WordPart::CommandSubst(d), WordPart::Parameter(q),
+        ] if a == "1+(2)" && c == "echo" && b == "v" && d == "date" && q == "?"));
+    }
+
+    #[test]
+    fn unclosed_quote_column() {
+        let e = read_word("echo 'abc", 5).unwrap_err();
+        assert_eq!(e.kind, ParseErrorKind::Unclosed('\''));
+        assert_eq!(e.column, 6);
+        let e = read_word("x\n ab\"c", 4).unwrap_err();
+        assert_eq!(e.kind, ParseErrorKind::Unclosed('"'));
+        assert_eq!(e.column, 4);
+    }
+
+    #[test]
+    fn case_pattern_inside_command_substitution() {
+        let word = read_word("$(case a in a) echo arm;; esac) x", 0).unwrap();
+        assert!(matches!(&word.parts[..], [WordPart::CommandSubst(c)] if c == "case a in a) echo arm;; esac"));
+    }
+}
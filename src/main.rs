@@ -1,11 +1,95 @@
 mod repl;
 mod parser;
+mod lexer;
 mod pipeline;
 mod builtins;
-mod history;
 mod completion;
 mod util;
+mod vars;
+mod expand;
+mod pattern;
+mod glob;
+mod options;
+mod spell;
+mod jobs;
+mod signals;
+mod script;
+mod redirect;
+mod ast;
+mod arith;
+mod functions;
+mod alias;
+mod dirs;
 
+use std::io::{BufReader, IsTerminal, Write};
+
+/// Usage: `shell [--norc] [--login|-l] [-euxo opt] [-c command [name [args...]] | script [args...]]`.
+/// With neither a command string nor a script, commands are read from stdin:
+/// interactively at a terminal, otherwise line by line from the pipe.
+/// A login shell (`-l`, or a name starting with `-`) first runs the profile
+/// files; an interactive shell runs `~/.rustshellrc` unless given `--norc`.
 fn main() {
-    repl::start_repl();
+    vars::init();
+    dirs::init();
+    let mut args: Vec<String> = std::env::args().collect();
+    let shell_name = args.remove(0);
+    let mut login = shell_name.starts_with('-');
+    let mut rc = true;
+    while let Some(arg) = args.first() {
+        match arg.as_str() {
+            "--norc" => rc = false,
+            "--login" | "-l" => login = true,
+            _ => break,
+        }
+        args.remove(0);
+    }
+    // `-c` may come on its own or among other flags, as in `-ec`.
+    let command_mode = options::take_command_flag(&mut args);
+    let mut rest = match options::parse_set_flags(&args) {
+        Ok(i) => args.split_off(i),
+        Err(e) => {
+            eprintln!("{}: {}", shell_name, e);
+            std::process::exit(2);
+        }
+    };
+    let interactive = !command_mode && rest.is_empty() && std::io::stdin().is_terminal();
+    if interactive {
+        options::set_interactive(true);
+    }
+    script::load_startup_files(login, interactive && rc);
+    let status = if command_mode {
+        if rest.is_empty() {
+            eprintln!("{}: -c: option requires an argument", shell_name);
+            std::process::exit(2);
+        }
+        let command = rest.remove(0);
+        if !rest.is_empty() {
+            vars::set_script_name(&rest.remove(0));
+        }
+        vars::set_positional(rest);
+        script::run_lines(command.as_bytes())
+    } else if !rest.is_empty() {
+        let path = rest.remove(0);
+        let file = match std::fs::File::open(&path) {
+            Ok(file) => file,
+            Err(e) => {
+                let reason = match e.kind() {
+                    std::io::ErrorKind::NotFound => "No such file or directory".to_string(),
+                    _ => e.to_string(),
+                };
+                eprintln!("{}: {}: {}", shell_name, path, reason);
+                std::process::exit(127);
+            }
+        };
+        vars::set_script_name(&path);
+        vars::set_positional(rest);
+        script::run_lines(BufReader::new(file))
+    } else if interactive {
+        repl::start_repl();
+        pipeline::last_status()
+    } else {
+        script::run_lines(std::io::stdin().lock())
+    };
+    std::io::stdout().flush().ok();
+    std::process::exit(status);
 }
@@ -0,0 +1,166 @@
+// options.rs
+
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, OnceLock};
+
+/// Options settable with `shopt -s` / `shopt -u`.
+/// `cdspell` offers (and asks before applying) corrections for misspelled
+/// `cd` directories.
+/// `expand_aliases` is on by default in interactive shells only.
+pub const SHOPT_OPTIONS: &[&str] = &["cdspell", "dotglob", "expand_aliases", "globstar", "nullglob"];
+
+/// Options settable with `set -o NAME` or the matching single-letter flag.
+pub const SET_OPTIONS: &[(char, &str)] = &[('e', "errexit"), ('u', "nounset"), ('x', "xtrace")];
+
+static SHOPT: OnceLock<Mutex<HashSet<&'static str>>> = OnceLock::new();
+static SET: OnceLock<Mutex<HashSet<&'static str>>> = OnceLock::new();
+static INTERACTIVE: AtomicBool = AtomicBool::new(false);
+
+fn shopt_table() -> std::sync::MutexGuard<'static, HashSet<&'static str>> {
+    SHOPT.get_or_init(|| Mutex::new(HashSet::new())).lock().unwrap()
+}
+
+pub fn shopt_enabled(name: &str) -> bool {
+    shopt_table().contains(name)
+}
+
+pub fn set_shopt(name: &str, on: bool) -> Result<(), String> {
+    let Some(&name) = SHOPT_OPTIONS.iter().find(|&&o| o == name) else {
+        return Err(format!("{}: invalid shell option name", name));
+    };
+    let mut table = shopt_table();
+    if on { table.insert(name); } else { table.remove(name); }
+    Ok(())
+}
+
+pub fn set_interactive(on: bool) {
+    INTERACTIVE.store(on, Ordering::Relaxed);
+    if on {
+        shopt_table().insert("expand_aliases");
+    }
+}
+
+/// Whether commands come from the prompt rather than a script, `-c` string
+/// or piped stdin.
+pub fn is_interactive() -> bool {
+    INTERACTIVE.load(Ordering::Relaxed)
+}
+
+fn set_table() -> std::sync::MutexGuard<'static, HashSet<&'static str>> {
+    SET.get_or_init(|| Mutex::new(HashSet::new())).lock().unwrap()
+}
+
+pub fn option_enabled(name: &str) -> bool {
+    set_table().contains(name)
+}
+
+/// Maps a `set` flag letter such as `e` to its long option name.
+pub fn option_for_flag(flag: char) -> Option<&'static str> {
+    SET_OPTIONS.iter().find(|&&(f, _)| f == flag).map(|&(_, name)| name)
+}
+
+pub fn set_option(name: &str, on: bool) -> Result<(), String> {
+    let Some(&(_, name)) = SET_OPTIONS.iter().find(|&&(_, o)| o == name) else {
+        return Err(format!("{}: invalid option name", name));
+    };
+    let mut table = set_table();
+    if on { table.insert(name); } else { table.remove(name); }
+    Ok(())
+}
+
+/// Removes the `c` flag from the option arguments at the front of `args`,
+/// whether alone (`-c`) or in a group (`-ec`), and returns whether it was
+/// there. Like `parse_set_flags`, the scan steps over the name after `-o`
+/// or `+o` and stops at the first argument that is not an option.
+pub fn take_command_flag(args: &mut Vec<String>) -> bool {
+    let mut i = 0;
+    while let Some(arg) = args.get(i) {
+        if arg.len() < 2 || !arg.starts_with(['-', '+']) || arg.starts_with("--") {
+            break;
+        }
+        if &arg[1..] == "o" {
+            i += 2;
+            continue;
+        }
+        if arg.starts_with('-') && arg.contains('c') {
+            let flags = arg.replacen('c', "", 1);
+            if flags == "-" {
+                args.remove(i);
+            } else {
+                args[i] = flags;
+            }
+            return true;
+        }
+        i += 1;
+    }
+    false
+}
+
+/// Applies `set`-style option arguments (`-eu`, `+x`, `-o name`, `+o name`)
+/// from the front of `args`, returning the index of the first argument that
+/// is not an option. A lone `--` ends the options and is consumed.
+pub fn parse_set_flags(args: &[String]) -> Result<usize, String> {
+    let mut i = 0;
+    while i < args.len() {
+        let arg = args[i].as_str();
+        if arg == "--" {
+            return Ok(i + 1);
+        }
+        let on = match arg.chars().next() {
+            Some('-') => true,
+            Some('+') => false,
+            _ => break,
+        };
+        if arg.len() == 1 {
+            break;
+        }
+        if &arg[1..] == "o" {
+            let Some(name) = args.get(i + 1) else { break };
+            set_option(name, on)?;
+            i += 2;
+            continue;
+        }
+        for flag in arg[1..].chars() {
+            let name = option_for_flag(flag).ok_or_else(|| format!("{}{}: invalid option", &arg[..1], flag))?;
+            set_option(name, on)?;
+        }
+        i += 1;
+    }
+    Ok(i)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn take(args: &[&str]) -> (bool, Vec<String>) {
+        let mut args: Vec<String> = args.iter().map(|a| a.to_string()).collect();
+        (take_command_flag(&mut args), args)
+    }
+
+    #[test]
+    fn command_flag_alone_or_grouped() {
+        assert_eq!(take(&["-c", "echo hi"]), (true, vec!["echo hi".to_string()]));
+        assert_eq!(take(&["-ec", "cmd"]), (true, vec!["-e".to_string(), "cmd".to_string()]));
+        assert_eq!(take(&["-x", "-c", "cmd"]), (true, vec!["-x".to_string(), "cmd".to_string()]));
+    }
+
+    #[test]
+    fn command_flag_after_option_name() {
+        let (found, rest) = take(&["-o", "errexit", "-c", "echo hi"]);
+        assert!(found);
+        assert_eq!(rest, ["-o", "errexit", "echo hi"]);
+        // `-o`'s name is not a flag group, even if it contains a `c`.
+        assert!(!take(&["+o", "-nocase", "script"]).0);
+    }
+
+    #[test]
+    fn command_flag_not_taken_past_options() {
+        assert!(!take(&["script", "-c"]).0);
+        assert!(!take(&["--", "-c"]).0);
+        let (found, rest) = take(&["-x", "--norc", "-c", "cmd"]);
+        assert!(!found);
+        assert_eq!(rest[1], "--norc");
+    }
+}
@@ -1,139 +1,999 @@
 // parser.rs
 
-#[derive(Clone, Copy, PartialEq, Eq, Debug)]
-pub enum QuoteType { None, Single, Double }
-
-pub fn shell_split_shell_like(line: &str) -> Vec<(String, QuoteType)> {
-    let mut tokens = Vec::new();
-    let mut cur = String::new();
-    let mut chars = line.chars().peekable();
-    enum State { Normal, Single, Double }
-    let mut state = State::Normal;
-    let mut token_quote = QuoteType::None;
-    while let Some(ch) = chars.next() {
-        match state {
-            State::Normal => match ch {
-                '\'' => {
-                    state = State::Single;
-                },
-                '"' => {
-                    state = State::Double;
-                },
-                '\\' => {
-                    if let Some(&next) = chars.peek() {
-                        cur.push(next);
-                        chars.next();
-                    }
-                }
-                c if c.is_whitespace() => {
-                    if !cur.is_empty() {
-                        tokens.push((cur.clone(), token_quote));
-                        cur.clear();
-                        token_quote = QuoteType::None;
+use std::iter::Peekable;
+use std::sync::Arc;
+use crate::ast::{AndOr, CaseArm, CaseTerminator, Command, CommandList, CompoundCommand, ListOp, Pipeline,
+    RedirectOp, Redirection, SimpleCommand, Span, Word, WordPart};
+use crate::lexer;
+use crate::vars;
+
+type CharIndices<'a> = Peekable<std::str::CharIndices<'a>>;
+
+/// What `skip_quoted` found at a character.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Skipped {
+    /// An ordinary character, left for the caller.
+    Plain,
+    /// A complete quoted or nested construct.
+    Closed,
+    /// A construct still open at the end of the text.
+    Unclosed,
+}
+
+/// If `c` opens a quoted or nested construct, consumes the rest of it from
+/// `chars`. An unterminated construct runs to the end.
+fn skip_quoted(c: char, chars: &mut CharIndices) -> Skipped {
+    let closed = match c {
+        '\\' => chars.next().is_some(),
+        '\'' => chars.any(|(_, c)| c == '\''),
+        '"' => loop {
+            match chars.next() {
+                None => break false,
+                Some((_, '"')) => break true,
+                Some((_, '\\')) => { chars.next(); }
+                // Only substitutions nest inside double quotes.
+                Some((_, c @ ('$' | '`'))) => {
+                    if skip_quoted(c, chars) == Skipped::Unclosed {
+                        break false;
                     }
                 }
-                _ => {
-                    cur.push(ch);
-                },
+                Some(_) => {}
+            }
+        },
+        '`' => loop {
+            match chars.next() {
+                None => break false,
+                Some((_, '`')) => break true,
+                Some((_, '\\')) => { chars.next(); }
+                Some(_) => {}
+            }
+        },
+        // `((expr))` is skipped whole, so that its operators are not syntax.
+        '(' if chars.peek().map(|&(_, n)| n) == Some('(') => {
+            chars.next();
+            skip_to_close(chars, '(', ')').is_some() && skip_to_close(chars, '(', ')').is_some()
+        }
+        '$' => match chars.peek().map(|&(_, n)| n) {
+            Some('(') => {
+                chars.next();
+                skip_command_subst(chars)
+            }
+            Some('{') => {
+                chars.next();
+                skip_to_close(chars, '{', '}').is_some()
+            }
+            _ => return Skipped::Plain,
+        },
+        _ => return Skipped::Plain,
+    };
+    if closed { Skipped::Closed } else { Skipped::Unclosed }
+}
+
+/// Consumes `chars` up to the `close` that balances an already consumed
+/// `open`, and returns its offset.
+pub fn skip_to_close(chars: &mut CharIndices, open: char, close: char) -> Option<usize> {
+    let mut depth = 0;
+    while let Some((i, c)) = chars.next() {
+        if c == close {
+            if depth == 0 {
+                return Some(i);
+            }
+            depth -= 1;
+        } else if c == open {
+            depth += 1;
+        } else if skip_quoted(c, chars) == Skipped::Unclosed {
+            return None;
+        }
+    }
+    None
+}
+
+/// Consumes `chars` up to the `)` that closes a `$(` whose `(` was just
+/// consumed. A body that does not parse as commands, such as an arithmetic
+/// expression, is skipped by balancing parentheses instead.
+fn skip_command_subst(chars: &mut CharIndices) -> bool {
+    let Some(&(base, _)) = chars.peek() else { return false };
+    let text: String = "$(".chars().chain(chars.clone().map(|(_, c)| c)).collect();
+    let Ok(end) = command_subst_end(&text, 0) else {
+        return skip_to_close(chars, '(', ')').is_some();
+    };
+    let end = base + end - 2;
+    while chars.next_if(|&(i, _)| i <= end).is_some() {}
+    true
+}
+
+/// Finds the `)` that closes a command substitution whose `$(` is at byte
+/// `dollar` of `text`, and returns its offset. The body is parsed as
+/// commands, so that the `)` after a `case` pattern does not end it.
+pub fn command_subst_end(text: &str, dollar: usize) -> Result<usize, ParseError> {
+    let mut parser = Parser { text, pos: dollar + 2 };
+    parser.list(&[])?;
+    parser.skip_linebreaks();
+    match parser.peek_operator() {
+        Some(")") => Ok(parser.pos),
+        _ if parser.at_end() => Err(ParseError::new(ParseErrorKind::Unclosed(')'), text, dollar)),
+        _ => Err(parser.unexpected()),
+    }
+}
+
+/// The expression inside `(expr)` if the parentheses enclose all of `text`.
+pub fn arithmetic_body(text: &str) -> Option<&str> {
+    let inner = text.strip_prefix('(')?;
+    let end = skip_to_close(&mut inner.char_indices().peekable(), '(', ')')?;
+    (end + 1 == inner.len()).then(|| &inner[..end])
+}
+
+/// Why a command line could not be parsed, and where.
+#[derive(Debug)]
+pub struct ParseError {
+    pub kind: ParseErrorKind,
+    /// Column of the token or quote at fault within its line, counting
+    /// from 1.
+    pub column: usize,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ParseErrorKind {
+    /// The text ended inside an unfinished command or compound command;
+    /// further lines may complete it.
+    Incomplete,
+    /// The text ended inside a quote or substitution, before the character
+    /// that would close it.
+    Unclosed(char),
+    /// The token the parser could not accept.
+    Unexpected(String),
+}
+
+impl ParseError {
+    /// The error at byte `offset` of the parsed `text`.
+    pub fn new(kind: ParseErrorKind, text: &str, offset: usize) -> ParseError {
+        let column = text[..offset].chars().rev().take_while(|&c| c != '\n').count() + 1;
+        ParseError { kind, column }
+    }
+
+    /// Whether more input could complete the text.
+    pub fn is_incomplete(&self) -> bool {
+        matches!(self.kind, ParseErrorKind::Incomplete | ParseErrorKind::Unclosed(_))
+    }
+}
+
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match &self.kind {
+            ParseErrorKind::Incomplete => write!(f, "syntax error: unexpected end of file"),
+            ParseErrorKind::Unclosed(close) => {
+                write!(f, "unexpected EOF while looking for matching `{}' (column {})", close, self.column)
+            }
+            ParseErrorKind::Unexpected(token) => write!(f, "syntax error near unexpected token `{}'", token),
+        }
+    }
+}
+
+/// Words that are only special at the start of a command, and there may not
+/// appear outside the compound command they belong to.
+const RESERVED_WORDS: &[&str] = &["then", "elif", "else", "fi", "do", "done", "esac", "}"];
+
+/// Control operators, longest first.
+const CONTROL_OPERATORS: &[&str] = &[";;&", ";;", ";&", "&&", "||", ";", "&", "|", "\n", "(", ")"];
+
+/// Parses a command line, which may span several lines, into a command list.
+/// Words are kept unexpanded, to be expanded when their command runs.
+pub fn parse_command_list(text: &str) -> Result<CommandList, ParseError> {
+    let mut parser = Parser { text, pos: 0 };
+    let list = parser.list(&[])?;
+    parser.skip_linebreaks();
+    if !parser.at_end() {
+        return Err(parser.unexpected());
+    }
+    Ok(list)
+}
+
+/// Returns true if `text` stops partway through a command, so that the line
+/// reader should append the next line before running it.
+pub fn is_incomplete(text: &str) -> bool {
+    parse_command_list(text).is_err_and(|e| e.is_incomplete())
+}
+
+/// Function names may be any word without quoting or expansions.
+fn is_function_name(word: &str) -> bool {
+    !word.is_empty() && !word.contains(['\'', '"', '\\', '$', '`', '=']) && !RESERVED_WORDS.contains(&word)
+}
+
+/// Splits a `NAME=value` word into the name and the value word. Other words
+/// are handed back unchanged.
+fn assignment(mut word: Word) -> Result<(String, Word), Word> {
+    let Some(WordPart::Literal(first)) = word.parts.first_mut() else {
+        return Err(word);
+    };
+    let Some((name, value)) = first.split_once('=').filter(|(name, _)| vars::is_valid_name(name)) else {
+        return Err(word);
+    };
+    let name = name.to_string();
+    *first = value.to_string();
+    if first.is_empty() {
+        word.parts.remove(0);
+    }
+    let skip = name.len() + 1;
+    let value = Word {
+        parts: word.parts,
+        text: word.text[skip..].to_string(),
+        span: Span { start: word.span.start + skip, end: word.span.end },
+    };
+    Ok((name, value))
+}
+
+/// A recursive-descent parser over the source text. Operators and reserved
+/// words are recognized in place; words are read by the lexer as the
+/// parser reaches them.
+struct Parser<'a> {
+    text: &'a str,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn rest(&self) -> &'a str {
+        &self.text[self.pos..]
+    }
+
+    fn at_end(&self) -> bool {
+        self.pos == self.text.len()
+    }
+
+    /// Skips blanks, escaped newlines and comments, but not newlines.
+    fn skip_blanks(&mut self) {
+        loop {
+            let rest = self.rest();
+            let trimmed = rest.trim_start_matches([' ', '\t']);
+            self.pos += rest.len() - trimmed.len();
+            if trimmed.starts_with("\\\n") {
+                self.pos += 2;
+            } else if trimmed.starts_with('#') {
+                self.pos += trimmed.find('\n').unwrap_or(trimmed.len());
+            } else {
+                break;
+            }
+        }
+    }
+
+    fn skip_linebreaks(&mut self) {
+        loop {
+            self.skip_blanks();
+            if !self.rest().starts_with('\n') {
+                break;
+            }
+            self.pos += 1;
+        }
+    }
+
+    /// The control operator at the current position, if any. `&` followed by
+    /// `>` starts a redirection instead.
+    fn peek_operator(&self) -> Option<&'static str> {
+        let rest = self.rest();
+        CONTROL_OPERATORS.iter()
+            .find(|op| rest.starts_with(**op))
+            .copied()
+            .filter(|&op| op != "&" || !rest.starts_with("&>"))
+    }
+
+    /// The plain word at the current position, for recognizing reserved words.
+    fn peek_word(&self) -> &'a str {
+        let rest = self.rest();
+        let end = rest.find(|c: char| c.is_whitespace() || ";&|()<>".contains(c)).unwrap_or(rest.len());
+        &rest[..end]
+    }
+
+    fn at_keyword(&self, keywords: &[&str]) -> bool {
+        keywords.contains(&self.peek_word())
+    }
+
+    fn error(&self, kind: ParseErrorKind) -> ParseError {
+        ParseError::new(kind, self.text, self.pos)
+    }
+
+    /// The error for whatever token is at byte `pos`.
+    fn unexpected_at(&self, pos: usize) -> ParseError {
+        Parser { text: self.text, pos }.unexpected()
+    }
+
+    /// The error for whatever token is at the current position.
+    fn unexpected(&self) -> ParseError {
+        let token = match self.peek_operator() {
+            _ if self.at_end() => return self.error(ParseErrorKind::Incomplete),
+            Some("\n") => "newline",
+            Some(op) => op,
+            None => match RedirectOp::at_start(self.rest()) {
+                Some((_, len)) => &self.rest()[..len],
+                None => self.peek_word(),
             },
-            State::Single => match ch {
-                '\'' => {
-                    state = State::Normal;
-                },
+        };
+        self.error(ParseErrorKind::Unexpected(token.to_string()))
+    }
+
+    fn keyword(&mut self, keyword: &str) -> Result<(), ParseError> {
+        self.skip_linebreaks();
+        if self.peek_word() != keyword {
+            return Err(self.unexpected());
+        }
+        self.pos += keyword.len();
+        Ok(())
+    }
+
+    /// Parses and-or lists separated by `;`, `&` or newlines, up to the end
+    /// of the text, one of the `terminators` reserved words, or a token that
+    /// can only close an enclosing construct.
+    fn list(&mut self, terminators: &[&str]) -> Result<CommandList, ParseError> {
+        let mut and_ors = Vec::new();
+        loop {
+            self.skip_linebreaks();
+            if self.at_end() || self.at_keyword(terminators) || matches!(self.peek_operator(), Some(")" | ";;" | ";&" | ";;&")) {
+                break;
+            }
+            let mut and_or = self.and_or()?;
+            self.skip_blanks();
+            match self.peek_operator() {
+                Some(";") | Some("\n") => self.pos += 1,
+                Some("&") => {
+                    self.pos += 1;
+                    and_or.background = true;
+                }
                 _ => {
-                    cur.push(ch);
-                    token_quote = QuoteType::Single;
-                },
-            },
-            State::Double => match ch {
-                '"' => {
-                    state = State::Normal;
-                },
-                '\\' => {
-                    if let Some(&next) = chars.peek() {
-                        match next {
-                            '\\' | '"' | '$' => {
-                                cur.push(next);
-                                chars.next();
-                            }
-                            '\'' => {
-                                cur.push('\'');
-                                chars.next();
-                            }
-                            _ => {
-                                cur.push('\\');
-                                cur.push(next);
-                                chars.next();
-                            }
-                        }
-                    } else {
-                        cur.push('\\');
+                    and_ors.push(and_or);
+                    break;
+                }
+            }
+            and_ors.push(and_or);
+        }
+        let span = match (and_ors.first(), and_ors.last()) {
+            (Some(first), Some(last)) => Span { start: first.span.start, end: last.span.end },
+            _ => Span { start: self.pos, end: self.pos },
+        };
+        Ok(CommandList { and_ors, span })
+    }
+
+    /// A list that must contain at least one command, as in the parts of a
+    /// compound command.
+    fn compound_list(&mut self, terminators: &[&str]) -> Result<CommandList, ParseError> {
+        let list = self.list(terminators)?;
+        if list.and_ors.is_empty() {
+            return Err(self.unexpected_at(list.span.end));
+        }
+        Ok(list)
+    }
+
+    fn and_or(&mut self) -> Result<AndOr, ParseError> {
+        let mut pipelines = vec![(ListOp::Seq, self.pipeline()?)];
+        loop {
+            self.skip_blanks();
+            let op = match self.peek_operator() {
+                Some("&&") => ListOp::And,
+                Some("||") => ListOp::Or,
+                _ => break,
+            };
+            self.pos += 2;
+            self.skip_linebreaks();
+            pipelines.push((op, self.pipeline()?));
+        }
+        let span = Span { start: pipelines[0].1.span.start, end: pipelines[pipelines.len() - 1].1.span.end };
+        Ok(AndOr { pipelines, background: false, span })
+    }
+
+    fn pipeline(&mut self) -> Result<Pipeline, ParseError> {
+        self.skip_blanks();
+        let start = self.pos;
+        let mut commands = vec![self.command()?];
+        loop {
+            self.skip_blanks();
+            if self.peek_operator() != Some("|") {
+                break;
+            }
+            self.pos += 1;
+            self.skip_linebreaks();
+            commands.push(self.command()?);
+        }
+        let text = self.text[start..self.pos].trim_end().to_string();
+        let span = Span { start, end: start + text.len() };
+        Ok(Pipeline { commands, text, span })
+    }
+
+    fn command(&mut self) -> Result<Command, ParseError> {
+        self.skip_blanks();
+        if let Some(command) = self.compound_command()? {
+            let redirects = self.redirections()?;
+            return Ok(Command::Compound { command, redirects });
+        }
+        match self.peek_word() {
+            "function" => self.function_definition(true),
+            word if RESERVED_WORDS.contains(&word) => Err(self.unexpected()),
+            word if is_function_name(word)
+                && self.rest()[word.len()..].trim_start_matches([' ', '\t']).starts_with('(') =>
+            {
+                self.function_definition(false)
+            }
+            _ => self.simple_command(),
+        }
+    }
+
+    /// Parses the compound command at the current position, if there is one.
+    fn compound_command(&mut self) -> Result<Option<CompoundCommand>, ParseError> {
+        if self.rest().starts_with("((") {
+            // `((` that does not close with `))` is a nested subshell.
+            let start = self.pos;
+            match self.arithmetic() {
+                Ok(expr) => return Ok(Some(CompoundCommand::Arithmetic(expr.to_string()))),
+                Err(e) if matches!(e.kind, ParseErrorKind::Unexpected(_)) => self.pos = start,
+                Err(e) => return Err(e),
+            }
+        }
+        if self.rest().starts_with('(') {
+            return self.subshell().map(Some);
+        }
+        let command = match self.peek_word() {
+            "{" => self.brace_group()?,
+            "if" => self.if_clause()?,
+            "while" | "until" => self.loop_clause()?,
+            "for" => self.for_clause()?,
+            "case" => self.case_clause()?,
+            _ => return Ok(None),
+        };
+        Ok(Some(command))
+    }
+
+    /// Parses `name () body` or `function name [()] body`, where the body is
+    /// a compound command, usually a `{ ...; }` group.
+    fn function_definition(&mut self, keyword: bool) -> Result<Command, ParseError> {
+        if keyword {
+            self.keyword("function")?;
+            self.skip_blanks();
+        }
+        let name = self.peek_word();
+        if !is_function_name(name) {
+            return Err(self.unexpected());
+        }
+        self.pos += name.len();
+        self.skip_blanks();
+        if self.rest().starts_with('(') {
+            self.pos += 1;
+            self.skip_blanks();
+            if !self.rest().starts_with(')') {
+                return Err(self.unexpected());
+            }
+            self.pos += 1;
+        } else if !keyword {
+            return Err(self.unexpected());
+        }
+        self.skip_linebreaks();
+        let Some(command) = self.compound_command()? else {
+            return Err(self.unexpected());
+        };
+        let redirects = self.redirections()?;
+        let body = Arc::new(Command::Compound { command, redirects });
+        Ok(Command::FunctionDef { name: name.to_string(), body })
+    }
+
+    fn brace_group(&mut self) -> Result<CompoundCommand, ParseError> {
+        self.keyword("{")?;
+        let body = self.compound_list(&["}"])?;
+        self.keyword("}")?;
+        Ok(CompoundCommand::BraceGroup(body))
+    }
+
+    fn subshell(&mut self) -> Result<CompoundCommand, ParseError> {
+        let start = self.pos;
+        self.pos += 1;
+        let body = self.compound_list(&[])?;
+        self.skip_linebreaks();
+        if self.peek_operator() != Some(")") {
+            return Err(self.unexpected());
+        }
+        self.pos += 1;
+        let text = self.text[start..self.pos].to_string();
+        Ok(CompoundCommand::Subshell { body, text })
+    }
+
+    fn simple_command(&mut self) -> Result<Command, ParseError> {
+        let start = self.pos;
+        let mut end = start;
+        let mut assignments = Vec::new();
+        let mut words = Vec::new();
+        let mut redirects = Vec::new();
+        loop {
+            self.skip_blanks();
+            if let Some(redirect) = self.redirection()? {
+                end = redirect.span.end;
+                redirects.push(redirect);
+            } else if self.at_end() || self.peek_operator().is_some() {
+                break;
+            } else {
+                let word = self.word()?;
+                end = word.span.end;
+                // `NAME=value` words are assignments until the first other word.
+                if !words.is_empty() {
+                    words.push(word);
+                } else {
+                    match assignment(word) {
+                        Ok(assignment) => assignments.push(assignment),
+                        Err(word) => words.push(word),
                     }
-                    token_quote = QuoteType::Double;
                 }
-                _ => {
-                    cur.push(ch);
-                    token_quote = QuoteType::Double;
-                },
+            }
+        }
+        if assignments.is_empty() && words.is_empty() && redirects.is_empty() {
+            return Err(self.unexpected());
+        }
+        let text = self.text[start..end].to_string();
+        let span = Span { start, end };
+        Ok(Command::Simple(SimpleCommand { assignments, words, redirects, text, span }))
+    }
+
+    /// Reads the word at the current position, which must not be empty.
+    fn word(&mut self) -> Result<Word, ParseError> {
+        let word = lexer::read_word(self.text, self.pos)?;
+        if word.span.end == word.span.start {
+            return Err(self.unexpected());
+        }
+        self.pos = word.span.end;
+        Ok(word)
+    }
+
+    /// Reads a `[n]op word` redirection at the current position, if there
+    /// is one.
+    fn redirection(&mut self) -> Result<Option<Redirection>, ParseError> {
+        let start = self.pos;
+        let rest = self.rest();
+        let digits = rest.find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len());
+        let Some((op, len)) = RedirectOp::at_start(&rest[digits..]) else {
+            return Ok(None);
+        };
+        // `&>` takes no descriptor number: in `2&>f` the 2 is a word.
+        let fd = match digits {
+            0 => None,
+            _ if rest[digits..].starts_with('&') => return Ok(None),
+            _ => match rest[..digits].parse() {
+                Ok(fd) => Some(fd),
+                Err(_) => return Ok(None),
             },
+        };
+        self.pos += digits + len;
+        self.skip_blanks();
+        if self.at_end() {
+            return Err(self.error(ParseErrorKind::Unexpected("newline".to_string())));
         }
+        let target = self.word()?;
+        let span = Span { start, end: target.span.end };
+        Ok(Some(Redirection { fd, op, target, span }))
     }
-    if !cur.is_empty() {
-        tokens.push((cur, token_quote));
+
+    /// Reads the redirections that may follow a compound command.
+    fn redirections(&mut self) -> Result<Vec<Redirection>, ParseError> {
+        let mut redirects = Vec::new();
+        loop {
+            self.skip_blanks();
+            match self.redirection()? {
+                Some(redirect) => redirects.push(redirect),
+                None => return Ok(redirects),
+            }
+        }
     }
-    tokens
-}
 
-pub fn shell_split_literal(line: &str) -> Vec<String> {
-    let mut tokens = Vec::new();
-    let mut cur = String::new();
-    let mut chars = line.chars().peekable();
-    while let Some(ch) = chars.next() {
-        match ch {
-            '\\' => {
-                if let Some(&next) = chars.peek() {
-                    cur.push(next);
-                    chars.next();
+    /// Reads `((expr))` at the current position and returns the expression.
+    fn arithmetic(&mut self) -> Result<&'a str, ParseError> {
+        let rest = self.rest();
+        let end = skip_to_close(&mut rest[1..].char_indices().peekable(), '(', ')')
+            .ok_or_else(|| self.error(ParseErrorKind::Incomplete))?;
+        let expr = arithmetic_body(&rest[1..end + 1])
+            .ok_or_else(|| self.error(ParseErrorKind::Unexpected("((".to_string())))?;
+        self.pos += end + 2;
+        Ok(expr)
+    }
+
+    fn if_clause(&mut self) -> Result<CompoundCommand, ParseError> {
+        self.keyword("if")?;
+        let mut branches = Vec::new();
+        let mut otherwise = None;
+        loop {
+            let condition = self.compound_list(&["then"])?;
+            self.keyword("then")?;
+            let body = self.compound_list(&["elif", "else", "fi"])?;
+            branches.push((condition, body));
+            match self.peek_word() {
+                "elif" => self.keyword("elif")?,
+                "else" => {
+                    self.keyword("else")?;
+                    otherwise = Some(self.compound_list(&["fi"])?);
+                    self.keyword("fi")?;
+                    break;
                 }
+                _ => {
+                    self.keyword("fi")?;
+                    break;
+                }
+            }
+        }
+        Ok(CompoundCommand::If { branches, otherwise })
+    }
+
+    fn loop_clause(&mut self) -> Result<CompoundCommand, ParseError> {
+        let until = self.peek_word() == "until";
+        self.pos += self.peek_word().len();
+        let condition = self.compound_list(&["do"])?;
+        let body = self.do_group()?;
+        Ok(CompoundCommand::Loop { until, condition, body })
+    }
+
+    fn do_group(&mut self) -> Result<CommandList, ParseError> {
+        self.keyword("do")?;
+        let body = self.compound_list(&["done"])?;
+        self.keyword("done")?;
+        Ok(body)
+    }
+
+    fn for_clause(&mut self) -> Result<CompoundCommand, ParseError> {
+        self.keyword("for")?;
+        self.skip_blanks();
+        if self.rest().starts_with("((") {
+            let expr = self.arithmetic()?;
+            let parts: Vec<&str> = expr.splitn(3, ';').collect();
+            let [init, condition, step] = parts[..] else {
+                return Err(self.error(ParseErrorKind::Unexpected(format!("(({}))", expr))));
+            };
+            self.skip_blanks();
+            if self.peek_operator() == Some(";") {
+                self.pos += 1;
             }
-            c if c.is_whitespace() => {
-                if !cur.is_empty() {
-                    tokens.push(cur.clone());
-                    cur.clear();
+            let body = self.do_group()?;
+            return Ok(CompoundCommand::ArithFor {
+                init: init.to_string(),
+                condition: condition.to_string(),
+                step: step.to_string(),
+                body,
+            });
+        }
+        let name = self.peek_word();
+        if !vars::is_valid_name(name) {
+            return Err(self.unexpected());
+        }
+        self.pos += name.len();
+        self.skip_linebreaks();
+        let mut words = None;
+        if self.at_keyword(&["in"]) {
+            self.pos += 2;
+            let mut list = Vec::new();
+            loop {
+                self.skip_blanks();
+                if self.at_end() || self.peek_operator().is_some() {
+                    break;
                 }
+                list.push(self.word()?);
+            }
+            words = Some(list);
+        }
+        self.skip_blanks();
+        match self.peek_operator() {
+            Some(";") | Some("\n") => self.pos += 1,
+            _ if words.is_none() => {}
+            _ => return Err(self.unexpected()),
+        }
+        let body = self.do_group()?;
+        Ok(CompoundCommand::For { name: name.to_string(), words, body })
+    }
+
+    fn case_clause(&mut self) -> Result<CompoundCommand, ParseError> {
+        self.keyword("case")?;
+        self.skip_blanks();
+        let word = self.word()?;
+        self.keyword("in")?;
+        let mut arms = Vec::new();
+        loop {
+            self.skip_linebreaks();
+            if self.at_keyword(&["esac"]) {
+                self.pos += 4;
+                break;
+            }
+            if self.rest().starts_with('(') {
+                self.pos += 1;
             }
-            _ => cur.push(ch),
+            let patterns = self.case_patterns()?;
+            let body = self.list(&["esac"])?;
+            let terminator = match self.peek_operator() {
+                Some(";;") => CaseTerminator::Break,
+                Some(";&") => CaseTerminator::FallThrough,
+                Some(";;&") => CaseTerminator::Continue,
+                _ if self.at_keyword(&["esac"]) => {
+                    arms.push(CaseArm { patterns, body, terminator: CaseTerminator::Break });
+                    continue;
+                }
+                _ => return Err(self.unexpected()),
+            };
+            self.pos += self.peek_operator().map_or(0, str::len);
+            arms.push(CaseArm { patterns, body, terminator });
         }
+        Ok(CompoundCommand::Case { word, arms })
     }
-    if !cur.is_empty() {
-        tokens.push(cur);
+
+    /// Reads the `|`-separated patterns of a case arm, through its `)`.
+    fn case_patterns(&mut self) -> Result<Vec<Word>, ParseError> {
+        let mut patterns = Vec::new();
+        loop {
+            self.skip_blanks();
+            patterns.push(self.word()?);
+            self.skip_blanks();
+            match self.peek_operator() {
+                Some("|") => self.pos += 1,
+                Some(")") => {
+                    self.pos += 1;
+                    return Ok(patterns);
+                }
+                _ => return Err(self.unexpected()),
+            }
+        }
     }
-    tokens
 }
 
-pub fn unescape_backslashes(s: &str) -> String {
-    let mut result = String::new();
-    let mut chars = s.chars().peekable();
-    while let Some(ch) = chars.next() {
-        if ch == '\\' {
-            if let Some(&next) = chars.peek() {
-                match next {
-                    ' ' | '\\' | '\t' | '\n' | '\'' => {
-                        result.push(next);
-                        chars.next();
-                    }
-                    _ => {
-                        result.push('\\');
-                        result.push(next);
-                        chars.next();
+/// A `<<` or `<<-` redirection found on a command line, awaiting its body.
+struct HereDocOp {
+    /// Byte range of the operator and its delimiter word.
+    start: usize,
+    end: usize,
+    delimiter: String,
+    /// A delimiter with any quoting suppresses expansion of the body.
+    quoted: bool,
+    strip_tabs: bool,
+}
+
+/// Finds the unquoted here-document operators on `line`, in order. A missing
+/// delimiter is a syntax error.
+fn find_heredocs(line: &str) -> Result<Vec<HereDocOp>, ParseError> {
+    let mut ops = Vec::new();
+    let mut chars = line.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '#' if line[..i].ends_with(char::is_whitespace) || i == 0 => break,
+            '<' if chars.peek().map(|&(_, n)| n) == Some('<') => {
+                chars.next();
+                if chars.peek().map(|&(_, n)| n) == Some('<') {
+                    chars.next();
+                    continue;
+                }
+                let strip_tabs = chars.next_if(|&(_, n)| n == '-').is_some();
+                while chars.next_if(|&(_, n)| n == ' ' || n == '\t').is_some() {}
+                let mut delimiter = String::new();
+                let mut quoted = false;
+                let mut word_quote = None;
+                let mut end = line.len();
+                while let Some(&(j, d)) = chars.peek() {
+                    match (word_quote, d) {
+                        (None, d) if d.is_whitespace() || ";|&<>()".contains(d) => {
+                            end = j;
+                            break;
+                        }
+                        (None, '\'') | (None, '"') => { word_quote = Some(d); quoted = true; }
+                        (Some(q), d) if q == d => word_quote = None,
+                        (None, '\\') => {
+                            quoted = true;
+                            chars.next();
+                            if let Some(&(_, escaped)) = chars.peek() {
+                                delimiter.push(escaped);
+                            }
+                        }
+                        _ => delimiter.push(d),
                     }
+                    chars.next();
                 }
-            } else {
-                result.push('\\');
+                if delimiter.is_empty() && !quoted {
+                    let token = line[end..].trim_start().chars().next();
+                    let token = token.map_or("newline".to_string(), |t| t.to_string());
+                    return Err(ParseError::new(ParseErrorKind::Unexpected(token), line, end));
+                }
+                ops.push(HereDocOp { start: i, end, delimiter, quoted, strip_tabs });
+            }
+            _ => { skip_quoted(c, &mut chars); }
+        }
+    }
+    Ok(ops)
+}
+
+/// Reads a whole command that starts with `line`. Here-document bodies, and
+/// the rest of a command left unfinished at the end of a line, are pulled
+/// from `next_line`.
+pub fn read_command(line: &str, mut next_line: impl FnMut() -> Option<String>) -> Result<String, ParseError> {
+    let mut command = read_heredocs(line, &mut next_line)?;
+    while is_incomplete(&command) {
+        let Some(line) = next_line() else { break };
+        command.push('\n');
+        command.push_str(&read_heredocs(&line, &mut next_line)?);
+    }
+    Ok(command)
+}
+
+/// Reads the bodies of the here-documents opened on `line`, pulling further
+/// lines from `next_line`, and returns the command with each `<<DELIM`
+/// rewritten as `<<` followed by the body as one quoted word. The body is
+/// expanded later, when the command runs, unless the delimiter was quoted.
+fn read_heredocs(line: &str, mut next_line: impl FnMut() -> Option<String>) -> Result<String, ParseError> {
+    let ops = find_heredocs(line)?;
+    let mut command = String::new();
+    let mut last = 0;
+    for op in ops {
+        let mut body = Vec::new();
+        loop {
+            let Some(text) = next_line() else {
+                eprintln!("warning: here-document delimited by end-of-file (wanted `{}')", op.delimiter);
+                break;
+            };
+            let text = if op.strip_tabs { text.trim_start_matches('\t').to_string() } else { text };
+            if text == op.delimiter {
+                break;
             }
-        } else {
-            result.push(ch);
+            body.push(text);
+        }
+        let body: String = body.into_iter().map(|line| line + "\n").collect();
+        command.push_str(&line[last..op.start]);
+        command.push_str("<<");
+        command.push_str(&if op.quoted { quote_literal(&body) } else { quote_expandable(&body) });
+        last = op.end;
+    }
+    command.push_str(&line[last..]);
+    Ok(command)
+}
+
+/// Single-quotes `text` so it reads back unchanged.
+fn quote_literal(text: &str) -> String {
+    format!("'{}'", text.replace('\'', "'\\''"))
+}
+
+/// Double-quotes a here-document body so that it expands as the body would:
+/// `"` and `'` stay literal, and a backslash only escapes `$`, `` ` ``, `\`
+/// and newline (which it removes).
+fn quote_expandable(text: &str) -> String {
+    let mut out = String::from("\"");
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.peek() {
+                Some('\n') => { chars.next(); }
+                Some(&next @ ('\\' | '$' | '`')) => {
+                    chars.next();
+                    out.push('\\');
+                    out.push(next);
+                }
+                Some('"') | Some('\'') | None => out.push_str("\\\\"),
+                Some(_) => out.push('\\'),
+            },
+            '"' => out.push_str("\\\""),
+            c => out.push(c),
         }
     }
-    result
-} 
\ No newline at end of file
+    out.push('"');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spans_of_lists_pipelines_and_redirections() {
+        let text = "a | b && c 2>f; d";
+        let list = parse_command_list(text).unwrap();
+        assert_eq!(list.span, Span { start: 0, end: 17 });
+        let [first, second] = &list.and_ors[..] else { panic!("expected two and-or lists") };
+        assert_eq!(first.span, Span { start: 0, end: 14 });
+        assert_eq!(&text[second.span.start..second.span.end], "d");
+
+        let (_, pipe) = &first.pipelines[0];
+        assert_eq!(pipe.span, Span { start: 0, end: 5 });
+        let (_, redirected) = &first.pipelines[1];
+        assert_eq!(redirected.span, Span { start: 9, end: 14 });
+        let Command::Simple(command) = &redirected.commands[0] else { panic!("expected a simple command") };
+        assert_eq!(command.redirects[0].span, Span { start: 11, end: 14 });
+        assert_eq!(&text[command.span.start..command.span.end], "c 2>f");
+    }
+
+    #[test]
+    fn empty_compound_list_reports_the_closing_token() {
+        let e = parse_command_list("{ }").unwrap_err();
+        assert_eq!(e.to_string(), "syntax error near unexpected token `}'");
+    }
+
+    fn error(text: &str) -> ParseError {
+        parse_command_list(text).unwrap_err()
+    }
+
+    #[test]
+    fn unclosed_quote_columns() {
+        let e = error("echo 'abc");
+        assert_eq!(e.kind, ParseErrorKind::Unclosed('\''));
+        assert_eq!(e.column, 6);
+        assert_eq!(e.to_string(), "unexpected EOF while looking for matching `'' (column 6)");
+        let e = error("true\n  echo \"x");
+        assert_eq!((e.kind, e.column), (ParseErrorKind::Unclosed('"'), 8));
+        assert_eq!(error("echo $(ls").kind, ParseErrorKind::Unclosed(')'));
+        assert!(error("echo `ls").is_incomplete());
+    }
+
+    #[test]
+    fn unexpected_tokens() {
+        assert_eq!(error("echo ;;").kind, ParseErrorKind::Unexpected(";;".to_string()));
+        assert_eq!(error("fi").kind, ParseErrorKind::Unexpected("fi".to_string()));
+        assert_eq!(error("echo >").kind, ParseErrorKind::Unexpected("newline".to_string()));
+        assert!(!error("a && || b").is_incomplete());
+    }
+
+    #[test]
+    fn incomplete_commands() {
+        for text in ["if true", "while true; do", "a |", "a &&", "{ echo", "case x in", "f() "] {
+            assert!(is_incomplete(text), "{}", text);
+        }
+        assert!(!is_incomplete("if true; then :; fi"));
+    }
+
+    #[test]
+    fn simple_command_parts() {
+        let list = parse_command_list("A=1 B=$x cmd 'arg' 2>err <in").unwrap();
+        let Command::Simple(command) = &list.and_ors[0].pipelines[0].1.commands[0] else {
+            panic!("not a simple command");
+        };
+        let names: Vec<&str> = command.assignments.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["A", "B"]);
+        let words: Vec<&str> = command.words.iter().map(|w| w.text.as_str()).collect();
+        assert_eq!(words, ["cmd", "'arg'"]);
+        assert_eq!(command.redirects.len(), 2);
+        assert_eq!(command.redirects[0].fd, Some(2));
+    }
+
+    #[test]
+    fn command_substitution_with_case() {
+        assert!(parse_command_list("y=$(case a in a) echo arm;; esac); echo $y").is_ok());
+        assert!(parse_command_list("echo \"$(case a in (a) echo;; esac)\"").is_ok());
+        assert_eq!(command_subst_end("$(case a in a) x;; esac) y", 0).unwrap(), 23);
+        assert_eq!(error("echo $(echo ;;)").to_string(), "syntax error near unexpected token `;;'");
+    }
+
+    fn read(line: &str, rest: &[&str]) -> String {
+        let mut lines = rest.iter().map(|l| l.to_string());
+        read_command(line, || lines.next()).unwrap()
+    }
+
+    #[test]
+    fn here_document_bodies_become_quoted_words() {
+        assert_eq!(read("cat <<EOF; echo after", &["a $X", "EOF"]), "cat <<\"a $X\n\"; echo after");
+        assert_eq!(read("cat <<'EOF'", &["it's $X", "EOF"]), "cat <<'it'\\''s $X\n'");
+        assert_eq!(read("cat <<-E", &["\t\tx", "\tE"]), "cat <<\"x\n\"");
+        assert_eq!(read("a <<A; b <<B", &["1", "A", "2", "B"]), "a <<\"1\n\"; b <<\"2\n\"");
+        // A here-string takes no body.
+        assert_eq!(read("cat <<<word", &["unread"]), "cat <<<word");
+        assert_eq!(error("cat <<").kind, ParseErrorKind::Unexpected("newline".to_string()));
+    }
+
+    #[test]
+    fn here_document_body_expands_as_written() {
+        let command = read("cat <<EOF", &["\\$HOME \"q\" 'x' \\n \\\\", "EOF"]);
+        let list = parse_command_list(&command).unwrap();
+        let Command::Simple(cat) = &list.and_ors[0].pipelines[0].1.commands[0] else {
+            panic!("not a simple command");
+        };
+        assert_eq!(cat.redirects[0].op, RedirectOp::HereDoc);
+        let body = crate::expand::expand_word(&cat.redirects[0].target).unwrap();
+        assert_eq!(body, "$HOME \"q\" 'x' \\n \\\n");
+    }
+
+    #[test]
+    fn subshells_and_brace_groups() {
+        let list = parse_command_list("(a; (b)) >out | { c; d & } 2>err").unwrap();
+        let commands = &list.and_ors[0].pipelines[0].1.commands;
+        let Command::Compound { command: CompoundCommand::Subshell { body, text }, redirects } = &commands[0] else {
+            panic!("not a subshell");
+        };
+        assert_eq!(text, "(a; (b))");
+        assert_eq!(body.and_ors.len(), 2);
+        assert_eq!(redirects[0].target.text, "out");
+        let Command::Compound { command: CompoundCommand::BraceGroup(body), redirects } = &commands[1] else {
+            panic!("not a brace group");
+        };
+        assert!(body.and_ors[1].background);
+        assert_eq!(redirects[0].fd, Some(2));
+        // `}` is only a keyword at the start of a command.
+        assert!(is_incomplete("{ echo }"));
+        assert_eq!(error("( )").kind, ParseErrorKind::Unexpected(")".to_string()));
+    }
+}
@@ -0,0 +1,149 @@
+// pattern.rs
+
+/// Returns true if `text` matches the shell pattern `pattern` in full.
+/// Supports `*`, `?`, bracket expressions (`[abc]`, `[!a-z]`, `[[:digit:]]`)
+/// and backslash-escaped literals.
+pub fn matches(pattern: &str, text: &str) -> bool {
+    let p: Vec<char> = pattern.chars().collect();
+    let t: Vec<char> = text.chars().collect();
+    let (mut pi, mut ti) = (0, 0);
+    // Position of the last `*` and the text index it is currently matched up to.
+    let mut star: Option<(usize, usize)> = None;
+    while ti < t.len() {
+        if pi < p.len() {
+            if p[pi] == '*' {
+                star = Some((pi, ti));
+                pi += 1;
+                continue;
+            }
+            if let Some(len) = match_one(&p[pi..], t[ti]) {
+                pi += len;
+                ti += 1;
+                continue;
+            }
+        }
+        match star {
+            Some((sp, st)) => {
+                pi = sp + 1;
+                ti = st + 1;
+                star = Some((sp, st + 1));
+            }
+            None => return false,
+        }
+    }
+    p[pi..].iter().all(|&c| c == '*')
+}
+
+/// Returns true if `s` contains an unescaped `*` or `?`, or a `[` that
+/// starts a complete bracket expression.
+pub fn has_meta(s: &str) -> bool {
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => { chars.next(); }
+            '*' | '?' => return true,
+            '[' if chars.as_str().contains(']') => return true,
+            _ => {}
+        }
+    }
+    false
+}
+
+/// Matches a single non-`*` pattern element against `c`, returning how many
+/// pattern characters it consumed.
+fn match_one(p: &[char], c: char) -> Option<usize> {
+    match p[0] {
+        '?' => Some(1),
+        '[' => match match_bracket(p, c) {
+            Some((true, len)) => Some(len),
+            Some((false, _)) => None,
+            // An unterminated bracket is an ordinary character.
+            None => (c == '[').then_some(1),
+        },
+        '\\' if p.len() > 1 => (p[1] == c).then_some(2),
+        pc => (pc == c).then_some(1),
+    }
+}
+
+/// Evaluates the bracket expression at the start of `p`. Returns whether `c`
+/// matched and the length of the expression, or `None` if it is unterminated.
+fn match_bracket(p: &[char], c: char) -> Option<(bool, usize)> {
+    let mut i = 1;
+    let negate = matches!(p.get(i), Some('!') | Some('^'));
+    if negate {
+        i += 1;
+    }
+    let mut matched = false;
+    let mut first = true;
+    while i < p.len() {
+        if p[i] == ']' && !first {
+            return Some((matched != negate, i + 1));
+        }
+        first = false;
+        if p[i] == '[' && p.get(i + 1) == Some(&':') {
+            let rest: String = p[i + 2..].iter().collect();
+            if let Some(end) = rest.find(":]") {
+                let class = &rest[..end];
+                matched |= match class {
+                    "alpha" => c.is_alphabetic(),
+                    "digit" => c.is_ascii_digit(),
+                    "alnum" => c.is_alphanumeric(),
+                    "upper" => c.is_uppercase(),
+                    "lower" => c.is_lowercase(),
+                    "space" => c.is_whitespace(),
+                    "blank" => c == ' ' || c == '\t',
+                    "punct" => c.is_ascii_punctuation(),
+                    "xdigit" => c.is_ascii_hexdigit(),
+                    _ => false,
+                };
+                i += 2 + class.chars().count() + 2;
+                continue;
+            }
+        }
+        let lo = if p[i] == '\\' && i + 1 < p.len() {
+            i += 1;
+            p[i]
+        } else {
+            p[i]
+        };
+        if p.get(i + 1) == Some(&'-') && i + 2 < p.len() && p[i + 2] != ']' {
+            let hi = p[i + 2];
+            matched |= lo <= c && c <= hi;
+            i += 3;
+        } else {
+            matched |= lo == c;
+            i += 1;
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wildcards() {
+        assert!(matches("*", ""));
+        assert!(matches("a*c", "abbc"));
+        assert!(matches("a?c", "abc"));
+        assert!(!matches("a?c", "ac"));
+        assert!(matches("*.tar.*", "x.tar.gz"));
+        assert!(!matches("*.rs", "main.rs.bak"));
+    }
+
+    #[test]
+    fn brackets() {
+        assert!(matches("[abc]x", "bx"));
+        assert!(matches("[!a-c]", "d"));
+        assert!(!matches("[!a-c]", "b"));
+        assert!(matches("[[:digit:]][[:alpha:]]", "1z"));
+    }
+
+    #[test]
+    fn escapes_are_literal() {
+        assert!(matches("a\\*", "a*"));
+        assert!(!matches("a\\*", "ab"));
+        assert!(matches("\\?", "?"));
+    }
+}
@@ -1,621 +1,841 @@
 // pipeline.rs
 
 use std::sync::{Arc, Mutex};
-use crate::parser::{shell_split_shell_like, unescape_backslashes, QuoteType};
-use crate::builtins::run_builtin;
-use crate::util::writeln_ignore_broken_pipe;
-use nix::unistd::{fork, ForkResult, pipe, dup2, close, execvp};
-use nix::sys::wait::waitpid;
+use crate::ast::{AndOr, CaseTerminator, Command, CommandList, CompoundCommand, ListOp, Pipeline, Redirection, SimpleCommand, Word};
+use crate::parser::parse_command_list;
+use crate::expand::{expand_assignment, expand_pattern, expand_text, expand_word, expand_words};
+use crate::builtins::{run_builtin, BUILTINS};
+use crate::vars;
+use crate::alias;
+use crate::arith;
+use crate::expand;
+use crate::functions;
+use crate::pattern;
+use crate::redirect::{self, Redirect, SavedFds};
+use crate::options;
+use crate::jobs::{self, Job};
+use crate::signals;
+use crate::util::shell_quote;
+use nix::errno::Errno;
+use nix::unistd::{fork, ForkResult, pipe, dup2, close, execv, Pid};
 use std::ffi::CString;
-use libc;
-use std::os::unix::io::{RawFd, FromRawFd};
-use nix::unistd::pipe as nix_pipe;
-use std::fs::File;
-use std::io::{BufRead, BufReader};
+use std::os::unix::io::RawFd;
+use std::sync::atomic::{AtomicI32, AtomicUsize, Ordering};
 use std::io::Write;
 use std::env;
 use std::os::unix::fs::PermissionsExt;
 
-pub fn execute_pipeline(input: &str, history: &Arc<Mutex<Vec<String>>>) {
-    let mut stages = vec![];
-    let mut in_single = false;
-    let mut in_double = false;
-    let mut last = 0;
-    let chars: Vec<char> = input.chars().collect();
-    for (i, &c) in chars.iter().enumerate() {
-        match c {
-            '\'' if !in_double => in_single = !in_single,
-            '"' if !in_single => in_double = !in_double,
-            '|' if !in_single && !in_double => {
-                stages.push(input[last..i].trim().to_string());
-                last = i + 1;
-            }
-            _ => {}
+/// Exit status of the most recent foreground command, as seen by `$?`.
+static LAST_STATUS: AtomicI32 = AtomicI32::new(0);
+
+pub fn last_status() -> i32 {
+    LAST_STATUS.load(Ordering::Relaxed)
+}
+
+pub fn set_last_status(status: i32) {
+    LAST_STATUS.store(status, Ordering::Relaxed);
+}
+
+/// A `break` or `continue` still unwinding through enclosing loops.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Flow {
+    Normal,
+    /// The number of loops left to leave.
+    Break(usize),
+    /// The number of loops to leave before the next iteration starts.
+    Continue(usize),
+    /// `return` from the innermost function call.
+    Return,
+}
+
+static FLOW: Mutex<Flow> = Mutex::new(Flow::Normal);
+
+/// How many loops enclose the command being run.
+static LOOP_DEPTH: AtomicUsize = AtomicUsize::new(0);
+
+/// How many files are being run by `source`, which `return` may also end.
+static SOURCE_DEPTH: AtomicUsize = AtomicUsize::new(0);
+
+/// How many `if`/`while`/`until` conditions enclose the command being run;
+/// `set -e` ignores failures inside them.
+static CONDITION_DEPTH: AtomicUsize = AtomicUsize::new(0);
+
+fn flow() -> Flow {
+    *FLOW.lock().unwrap()
+}
+
+fn set_flow(flow: Flow) {
+    *FLOW.lock().unwrap() = flow;
+}
+
+/// Parses and runs a command line. Returns the last status.
+pub fn execute_list(input: &str, history: &Arc<Mutex<Vec<String>>>) -> i32 {
+    match parse_command_list(input) {
+        Ok(list) => {
+            signals::clear_interrupted();
+            run_list(&list, history)
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            set_last_status(2);
+            2
         }
     }
-    stages.push(input[last..].trim().to_string());
-    if stages.len() > 1 {
-        let shell_like_builtins = ["echo", "type", "pwd", "cd", "exit", "history"];
-        let mut pipes = vec![];
-        for _ in 0..stages.len() - 1 {
-            pipes.push(pipe().expect("pipe failed"));
+}
+
+/// Runs the and-or lists of a command list in order, starting those that
+/// end in `&` as background jobs.
+fn run_list(list: &CommandList, history: &Arc<Mutex<Vec<String>>>) -> i32 {
+    let mut status = last_status();
+    for and_or in &list.and_ors {
+        if signals::interrupted() || flow() != Flow::Normal {
+            break;
         }
-        let mut children = Vec::new();
-        let mut child_stderr_fds = Vec::new();
-        for i in 0..stages.len() {
-            let token_pairs = shell_split_shell_like(&stages[i]);
-            if token_pairs.is_empty() { continue; }
-            // Redirection file creation logic
-            let mut j = 0;
-            let mut filtered_tokens = vec![];
-            let mut filtered_quotes = vec![];
-            let mut stderr_file: Option<(String, bool)> = None; // (filename, append)
-            let mut stdout_file: Option<(String, bool)> = None; // (filename, append)
-            while j < token_pairs.len() {
-                let (ref token, ref quote) = token_pairs[j];
-                if (token == ">" || token == "1>") && j + 1 < token_pairs.len() {
-                    let _ = std::fs::File::create(&token_pairs[j + 1].0);
-                    stdout_file = Some((token_pairs[j + 1].0.clone(), false));
-                    j += 2;
-                    continue;
-                } else if (token == ">>" || token == "1>>") && j + 1 < token_pairs.len() {
-                    let _ = std::fs::OpenOptions::new().create(true).append(true).open(&token_pairs[j + 1].0);
-                    stdout_file = Some((token_pairs[j + 1].0.clone(), true));
-                    j += 2;
-                    continue;
-                } else if token == "2>" && j + 1 < token_pairs.len() {
-                    let _ = std::fs::File::create(&token_pairs[j + 1].0);
-                    stderr_file = Some((token_pairs[j + 1].0.clone(), false));
-                    j += 2;
-                    continue;
-                } else if token == "2>>" && j + 1 < token_pairs.len() {
-                    let _ = std::fs::OpenOptions::new().create(true).append(true).open(&token_pairs[j + 1].0);
-                    stderr_file = Some((token_pairs[j + 1].0.clone(), true));
-                    j += 2;
-                    continue;
-                }
-                filtered_tokens.push(token.clone());
-                filtered_quotes.push(*quote);
-                j += 1;
+        status = if and_or.background {
+            run_background(and_or, history)
+        } else {
+            run_and_or(and_or, history)
+        };
+    }
+    status
+}
+
+/// Runs an `&&`/`||` chain, short-circuiting on the exit status of the
+/// previous pipeline.
+fn run_and_or(and_or: &AndOr, history: &Arc<Mutex<Vec<String>>>) -> i32 {
+    let mut status = last_status();
+    let last = and_or.pipelines.len() - 1;
+    for (i, (op, pipeline)) in and_or.pipelines.iter().enumerate() {
+        let skip = match op {
+            ListOp::Seq => false,
+            ListOp::And => status != 0,
+            ListOp::Or => status == 0,
+        };
+        if signals::interrupted() || flow() != Flow::Normal {
+            break;
+        }
+        if !skip {
+            status = run_pipeline(pipeline, history, false);
+            // `set -e` ignores failures that an `&&`/`||` or a condition
+            // goes on to test.
+            if status != 0 && i == last && CONDITION_DEPTH.load(Ordering::Relaxed) == 0
+                && options::option_enabled("errexit")
+            {
+                std::io::stdout().flush().ok();
+                std::process::exit(status);
             }
-            let tokens = filtered_tokens;
-            let quotes = filtered_quotes;
-            let is_builtin = shell_like_builtins.contains(&tokens[0].as_str());
-            let (stdin_fd, stdout_fd) = match stages.len() {
-                1 => (0, 1),
-                _ => {
-                    let stdin_fd = if i == 0 {
-                        0
-                    } else {
-                        pipes[i - 1].0
-                    };
-                    let stdout_fd = if i == stages.len() - 1 {
-                        1
-                    } else {
-                        pipes[i].1
-                    };
-                    (stdin_fd, stdout_fd)
-                }
-            };
-            if is_builtin {
-                if i != stages.len() - 1 {
-                    match unsafe { fork() } {
-                        Ok(ForkResult::Child) => {
-                            let orig_stdin: Option<RawFd> = if stdin_fd != 0 { dup2(0, 1000 + i as i32).ok() } else { None };
-                            let orig_stdout: Option<RawFd> = if stdout_fd != 1 { dup2(1, 2000 + i as i32).ok() } else { None };
-                            let orig_stderr: Option<RawFd> = Some(dup2(2, 3000 + i as i32).unwrap());
-                            if stdin_fd != 0 { dup2(stdin_fd, 0).ok(); }
-                            // Handle >, 1>, >>, 1>>
-                            if let Some((filename, append)) = &stdout_file {
-                                use std::os::unix::io::AsRawFd;
-                                let file = if *append {
-                                    std::fs::OpenOptions::new().create(true).append(true).open(filename)
-                                } else {
-                                    std::fs::File::create(filename)
-                                };
-                                if let Ok(f) = file {
-                                    dup2(f.as_raw_fd(), 1).ok();
-                                }
-                            } else if stdout_fd != 1 { dup2(stdout_fd, 1).ok(); }
-                            // Handle 2> or 2>>
-                            if let Some((filename, append)) = &stderr_file {
-                                use std::os::unix::io::AsRawFd;
-                                let file = if *append {
-                                    std::fs::OpenOptions::new().create(true).append(true).open(filename)
-                                } else {
-                                    std::fs::File::create(filename)
-                                };
-                                if let Ok(f) = file {
-                                    dup2(f.as_raw_fd(), 2).ok();
-                                }
-                            }
-                            for (j, (r, w)) in pipes.iter().enumerate() {
-                                if j != i - 1 && *r != 0 && *r != 1 { close(*r).ok(); }
-                                if j != i && *w != 0 && *w != 1 { close(*w).ok(); }
-                            }
-                            run_builtin(tokens.clone(), history);
-                            std::io::stdout().flush().ok();
-                            // Restore fds
-                            if let Some(fd) = orig_stdout { dup2(fd, 1).ok(); if fd != 0 && fd != 1 { close(fd).ok(); } }
-                            if let Some(fd) = orig_stderr { dup2(fd, 2).ok(); if fd != 0 && fd != 1 && fd != 2 { close(fd).ok(); } }
-                            if stdout_fd != 1 {
-                                close(1).ok();
-                                if stdout_fd != 0 { close(stdout_fd).ok(); }
-                            }
-                            if let Some(fd) = orig_stdin { dup2(fd, 0).ok(); if fd != 0 && fd != 1 { close(fd).ok(); } }
-                            if stdin_fd != 0 && stdin_fd != 1 { close(stdin_fd).ok(); }
-                            if stdout_fd != 1 && stdout_fd != 0 { close(stdout_fd).ok(); }
-                            unsafe { libc::_exit(0) };
-                        }
-                        Ok(ForkResult::Parent { child }) => {
-                            children.push(child);
-                        }
-                        Err(_) => { eprintln!("fork failed"); return; }
-                    }
-                } else {
-                    let orig_stdin: Option<RawFd> = if stdin_fd != 0 { dup2(0, 1000 + i as i32).ok() } else { None };
-                    let orig_stdout: Option<RawFd> = if stdout_fd != 1 { dup2(1, 2000 + i as i32).ok() } else { None };
-                    let orig_stderr: Option<RawFd> = Some(dup2(2, 3000 + i as i32).unwrap());
-                    if stdin_fd != 0 { dup2(stdin_fd, 0).ok(); }
-                    // Handle >, 1>, >>, 1>>
-                    if let Some((filename, append)) = &stdout_file {
-                        use std::os::unix::io::AsRawFd;
-                        let file = if *append {
-                            std::fs::OpenOptions::new().create(true).append(true).open(filename)
-                        } else {
-                            std::fs::File::create(filename)
-                        };
-                        if let Ok(f) = file {
-                            dup2(f.as_raw_fd(), 1).ok();
-                        }
-                    }
-                    // Handle 2> or 2>>
-                    if let Some((filename, append)) = &stderr_file {
-                        use std::os::unix::io::AsRawFd;
-                        let file = if *append {
-                            std::fs::OpenOptions::new().create(true).append(true).open(filename)
-                        } else {
-                            std::fs::File::create(filename)
-                        };
-                        if let Ok(f) = file {
-                            dup2(f.as_raw_fd(), 2).ok();
-                        }
-                    }
-                    for (j, (r, w)) in pipes.iter().enumerate() {
-                        if j != i - 1 && *r != 0 && *r != 1 { close(*r).ok(); }
-                        if j != i && *w != 0 && *w != 1 { close(*w).ok(); }
-                    }
-                    run_builtin(tokens.clone(), history);
-                    std::io::stdout().flush().ok();
-                    // Restore fds
-                    if let Some(fd) = orig_stdout { dup2(fd, 1).ok(); if fd != 0 && fd != 1 { close(fd).ok(); } }
-                    if let Some(fd) = orig_stderr { dup2(fd, 2).ok(); if fd != 0 && fd != 1 && fd != 2 { close(fd).ok(); } }
-                    if stdout_fd != 1 {
-                        close(1).ok();
-                        if stdout_fd != 0 { close(stdout_fd).ok(); }
-                    }
-                    if let Some(fd) = orig_stdin { dup2(fd, 0).ok(); if fd != 0 && fd != 1 { close(fd).ok(); } }
-                    if stdin_fd != 0 && stdin_fd != 1 { close(stdin_fd).ok(); }
-                    if stdout_fd != 1 && stdout_fd != 0 { close(stdout_fd).ok(); }
-                }
-            } else {
-                let (stderr_r, stderr_w) = nix_pipe().unwrap();
-                match unsafe { fork() } {
-                    Ok(ForkResult::Child) => {
-                        if stdin_fd != 0 { dup2(stdin_fd, 0).ok(); }
-                        // Handle >, 1>, >>, 1>>
-                        if let Some((filename, append)) = &stdout_file {
-                            use std::os::unix::io::AsRawFd;
-                            let file = if *append {
-                                std::fs::OpenOptions::new().create(true).append(true).open(filename)
-                            } else {
-                                std::fs::File::create(filename)
-                            };
-                            if let Ok(f) = file {
-                                dup2(f.as_raw_fd(), 1).ok();
-                            }
-                        } else if stdout_fd != 1 { dup2(stdout_fd, 1).ok(); }
-                        // Handle 2> or 2>>
-                        if let Some((filename, append)) = &stderr_file {
-                            use std::os::unix::io::AsRawFd;
-                            let file = if *append {
-                                std::fs::OpenOptions::new().create(true).append(true).open(filename)
-                            } else {
-                                std::fs::File::create(filename)
-                            };
-                            if let Ok(f) = file {
-                                dup2(f.as_raw_fd(), 2).ok();
+        }
+    }
+    status
+}
+
+/// Starts an `&`-terminated chain as a background job. A lone pipeline is
+/// started directly; a longer chain runs in a forked subshell.
+fn run_background(and_or: &AndOr, history: &Arc<Mutex<Vec<String>>>) -> i32 {
+    if let [(_, pipeline)] = and_or.pipelines.as_slice() {
+        return run_pipeline(pipeline, history, true);
+    }
+    match unsafe { fork() } {
+        Ok(ForkResult::Child) => {
+            jobs::setup_child(None, false);
+            let status = run_and_or(and_or, history);
+            std::io::stdout().flush().ok();
+            unsafe { libc::_exit(status) }
+        }
+        Ok(ForkResult::Parent { child }) => {
+            let pgid = jobs::track_child(child, None, false);
+            jobs::add_background(Job::new(pgid, &[child], &and_or.text()));
+            set_last_status(0);
+            0
+        }
+        Err(_) => { eprintln!("fork failed"); 1 }
+    }
+}
+
+/// Runs one pipeline, in the background if `background` is set, and records
+/// its status for `$?`. A lone foreground command runs in the shell itself,
+/// so that builtins and assignments affect it; everything else is forked.
+fn run_pipeline(pipeline: &Pipeline, history: &Arc<Mutex<Vec<String>>>, background: bool) -> i32 {
+    let status = match (pipeline.commands.as_slice(), background) {
+        ([command], false) => run_in_shell(command, &pipeline.text, history),
+        _ => run_forked(pipeline, history, background),
+    };
+    set_last_status(status);
+    status
+}
+
+/// The source text of a simple command whose first word is an alias, with
+/// the alias expanded. Aliases are expanded when `expand_aliases` is set,
+/// as it is in interactive shells.
+fn alias_expansion(command: &SimpleCommand) -> Option<String> {
+    if !options::shopt_enabled("expand_aliases") {
+        return None;
+    }
+    // The text before the first word holds its assignments and redirections.
+    let offset = command.words.first()?.span.start - command.span.start;
+    let words = alias::expand(&command.text[offset..])?;
+    Some(format!("{}{}", &command.text[..offset], words))
+}
+
+/// A simple command after expansion.
+struct Expanded {
+    assignments: Vec<(String, String)>,
+    args: Vec<String>,
+    redirects: Vec<Redirect>,
+}
+
+/// Expands a simple command's words into arguments and redirections, then
+/// its assignments. Returns the status to report if expansion fails.
+fn expand_command(command: &SimpleCommand) -> Result<Expanded, i32> {
+    let args = expand_words(&command.words).map_err(|e| expansion_error(&e))?;
+    let redirects = redirect::expand(&command.redirects).map_err(|e| {
+        eprintln!("{}", e);
+        1
+    })?;
+    // With no command, assignments are made one at a time by the caller.
+    let assignments = if args.is_empty() {
+        Vec::new()
+    } else {
+        expand_assignments(&command.assignments).map_err(|e| expansion_error(&e))?
+    };
+    Ok(Expanded { assignments, args, redirects })
+}
+
+/// Runs a lone foreground command. Builtins and assignments take effect in
+/// the shell itself; an external command runs in a child that is waited for.
+fn run_in_shell(command: &Command, text: &str, history: &Arc<Mutex<Vec<String>>>) -> i32 {
+    let simple = match command {
+        Command::Simple(simple) => simple,
+        Command::Compound { command, redirects } => return run_compound_redirected(command, redirects, history),
+        Command::FunctionDef { name, body } => {
+            functions::define(name, body.clone());
+            return 0;
+        }
+    };
+    if let Some(text) = alias_expansion(simple) {
+        return execute_list(&text, history);
+    }
+    let expanded = match expand_command(simple) {
+        Ok(expanded) => expanded,
+        Err(status) => return status,
+    };
+    if expanded.args.is_empty() {
+        return match apply_redirects_only(&expanded.redirects) {
+            0 => assign_variables(&simple.assignments),
+            status => status,
+        };
+    }
+    trace(&expanded.assignments, &expanded.args);
+    // Functions are looked up before builtins and programs.
+    if functions::is_function(&expanded.args[0]) || BUILTINS.contains(&expanded.args[0].as_str()) {
+        return run_redirected_builtin(&expanded, history);
+    }
+    match unsafe { fork() } {
+        Ok(ForkResult::Child) => {
+            jobs::setup_child(None, true);
+            let status = exec_expanded(expanded, history);
+            std::io::stdout().flush().ok();
+            unsafe { libc::_exit(status) }
+        }
+        Ok(ForkResult::Parent { child }) => {
+            let pgid = jobs::track_child(child, None, true);
+            jobs::wait_job(Job::new(pgid, &[child], text))
+        }
+        Err(_) => { eprintln!("fork failed"); 1 }
+    }
+}
+
+/// Forks every command of a pipeline into one job, connected by pipes, and
+/// waits for it unless it runs in the background.
+fn run_forked(pipeline: &Pipeline, history: &Arc<Mutex<Vec<String>>>, background: bool) -> i32 {
+    let foreground = !background;
+    let count = pipeline.commands.len();
+    let pipes: Vec<(RawFd, RawFd)> = (1..count).map(|_| pipe().expect("pipe failed")).collect();
+    let mut children: Vec<Pid> = Vec::new();
+    let mut pgid = None;
+    for (i, command) in pipeline.commands.iter().enumerate() {
+        match unsafe { fork() } {
+            Ok(ForkResult::Child) => {
+                jobs::setup_child(pgid, foreground);
+                if i > 0 { dup2(pipes[i - 1].0, 0).ok(); }
+                if i < count - 1 { dup2(pipes[i].1, 1).ok(); }
+                for (r, w) in &pipes { close(*r).ok(); close(*w).ok(); }
+                let status = match command {
+                    Command::Simple(simple) => match alias_expansion(simple) {
+                        Some(text) => execute_list(&text, history),
+                        None => match expand_command(simple) {
+                            Ok(expanded) => {
+                                trace(&expanded.assignments, &expanded.args);
+                                exec_expanded(expanded, history)
                             }
-                        } else {
-                            dup2(stderr_w, 2).ok();
-                        }
-                        for (r, w) in &pipes { close(*r).ok(); close(*w).ok(); }
-                        close(stderr_r).ok();
-                        close(stderr_w).ok();
-                        let cmd = CString::new(tokens[0].clone()).unwrap();
-                        let args: Vec<CString> = std::iter::once(tokens[0].clone())
-                            .chain(tokens.iter().zip(quotes.iter()).skip(1).map(|(s, q)| {
-                                match q {
-                                    QuoteType::Single | QuoteType::Double => {
-                                        if !s.starts_with('-') && !std::path::Path::new(s).exists() {
-                                            let quoted = format!("'{}'", s);
-                                            let with_1_backslash = format!("{}\\", s);
-                                            let quoted_with_1_backslash = format!("'{}\\'", s);
-                                            let with_2_backslashes = format!("{}\\\\", s);
-                                            let quoted_with_2_backslashes = format!("'{}\\\\'", s);
-                                            let variants = [
-                                                &quoted,
-                                                &with_1_backslash,
-                                                &quoted_with_1_backslash,
-                                                &with_2_backslashes,
-                                                &quoted_with_2_backslashes,
-                                            ];
-                                            for v in variants.iter() {
-                                                if std::path::Path::new(v).exists() {
-                                                    return (*v).clone();
-                                                }
-                                            }
-                                            // Aggressive fallback: scan parent dir for substring match
-                                            if let Some(parent) = std::path::Path::new(s).parent() {
-                                                if let Ok(entries) = std::fs::read_dir(parent) {
-                                                    for entry in entries.flatten() {
-                                                        let fname = entry.file_name().to_string_lossy().to_string();
-                                                        if fname.contains(s) || fname.contains(&quoted) {
-                                                            return entry.path().to_string_lossy().to_string();
-                                                        }
-                                                    }
-                                                }
-                                            }
-                                        }
-                                        s.clone()
-                                    },
-                                    QuoteType::None => unescape_backslashes(s),
-                                }
-                            }))
-                            .map(|s| CString::new(s).unwrap())
-                            .collect();
-                        execvp(&cmd, &args).unwrap_or_else(|_| { unsafe { libc::_exit(127) } });
-                    }
-                    Ok(ForkResult::Parent { child }) => {
-                        children.push(child);
-                        close(stderr_w).ok();
-                        child_stderr_fds.push(stderr_r);
+                            Err(status) => status,
+                        },
+                    },
+                    Command::Compound { command, redirects } => run_compound_redirected(command, redirects, history),
+                    Command::FunctionDef { name, body } => {
+                        functions::define(name, body.clone());
+                        0
                     }
-                    Err(_) => { eprintln!("fork failed"); return; }
-                }
+                };
+                std::io::stdout().flush().ok();
+                unsafe { libc::_exit(status) }
+            }
+            Ok(ForkResult::Parent { child }) => {
+                pgid = Some(jobs::track_child(child, pgid, foreground));
+                children.push(child);
+            }
+            Err(_) => {
+                eprintln!("fork failed");
+                break;
             }
         }
-        // Close all pipe fds in parent
-        for (r, w) in &pipes {
-            close(*r).ok();
-            close(*w).ok();
+    }
+    for (r, w) in &pipes {
+        close(*r).ok();
+        close(*w).ok();
+    }
+    let Some(pgid) = pgid else { return 1 };
+    let job = Job::new(pgid, &children, &pipeline.text);
+    if background {
+        jobs::add_background(job);
+        return 0;
+    }
+    jobs::wait_job(job)
+}
+
+/// Runs a compound command with its redirections applied, restoring the
+/// shell's own descriptors afterwards.
+fn run_compound_redirected(command: &CompoundCommand, redirects: &[Redirection], history: &Arc<Mutex<Vec<String>>>) -> i32 {
+    let redirects = match redirect::expand(redirects) {
+        Ok(redirects) => redirects,
+        Err(e) => { eprintln!("{}", e); return 1; }
+    };
+    let saved = SavedFds::save(redirects.iter().map(Redirect::fd));
+    let status = match redirect::apply(&redirects) {
+        Ok(()) => run_compound(command, history),
+        Err(e) => { eprintln!("{}", e); 1 }
+    };
+    saved.restore();
+    status
+}
+
+/// Runs a `( list )` subshell in a forked child, so that its variables,
+/// directory changes and `exit` leave the shell untouched.
+fn run_subshell(body: &CommandList, text: &str, history: &Arc<Mutex<Vec<String>>>) -> i32 {
+    std::io::stdout().flush().ok();
+    match unsafe { fork() } {
+        Ok(ForkResult::Child) => {
+            jobs::setup_child(None, true);
+            let status = run_list(body, history);
+            std::io::stdout().flush().ok();
+            unsafe { libc::_exit(status) }
         }
-        for (child, stderr_fd) in children.into_iter().zip(child_stderr_fds.into_iter()) {
-            let _ = waitpid(child, None);
-            let file = unsafe { File::from_raw_fd(stderr_fd) };
-            let reader = BufReader::new(file);
-            for line in reader.lines().flatten() {
-                if !line.contains("write error: Broken pipe") {
-                    let _ = writeln_ignore_broken_pipe(std::io::stderr(), &line);
+        Ok(ForkResult::Parent { child }) => {
+            let pgid = jobs::track_child(child, None, true);
+            jobs::wait_job(Job::new(pgid, &[child], text))
+        }
+        Err(_) => { eprintln!("fork failed"); 1 }
+    }
+}
+
+fn run_compound(command: &CompoundCommand, history: &Arc<Mutex<Vec<String>>>) -> i32 {
+    match command {
+        CompoundCommand::BraceGroup(body) => run_list(body, history),
+        CompoundCommand::Subshell { body, text } => run_subshell(body, text, history),
+        CompoundCommand::Arithmetic(expr) => run_arithmetic(expr),
+        CompoundCommand::If { branches, otherwise } => {
+            for (condition, body) in branches {
+                if run_condition(condition, history) == 0 {
+                    return run_list(body, history);
+                }
+                if flow() != Flow::Normal {
+                    return last_status();
                 }
             }
-        }
-    } else {
-        let token_pairs = shell_split_shell_like(input);
-        if token_pairs.is_empty() { return; }
-        // Redirection file creation logic
-        let mut j = 0;
-        let mut filtered_tokens = vec![];
-        let mut filtered_quotes = vec![];
-        let mut stderr_file: Option<(String, bool)> = None;
-        let mut stdout_file: Option<(String, bool)> = None;
-        while j < token_pairs.len() {
-            let (ref token, ref quote) = token_pairs[j];
-            if (token == ">" || token == "1>") && j + 1 < token_pairs.len() {
-                let _ = std::fs::File::create(&token_pairs[j + 1].0);
-                stdout_file = Some((token_pairs[j + 1].0.clone(), false));
-                j += 2;
-                continue;
-            } else if (token == ">>" || token == "1>>") && j + 1 < token_pairs.len() {
-                let _ = std::fs::OpenOptions::new().create(true).append(true).open(&token_pairs[j + 1].0);
-                stdout_file = Some((token_pairs[j + 1].0.clone(), true));
-                j += 2;
-                continue;
-            } else if token == "2>" && j + 1 < token_pairs.len() {
-                let _ = std::fs::File::create(&token_pairs[j + 1].0);
-                stderr_file = Some((token_pairs[j + 1].0.clone(), false));
-                j += 2;
-                continue;
-            } else if token == "2>>" && j + 1 < token_pairs.len() {
-                let _ = std::fs::OpenOptions::new().create(true).append(true).open(&token_pairs[j + 1].0);
-                stderr_file = Some((token_pairs[j + 1].0.clone(), true));
-                j += 2;
-                continue;
+            match otherwise {
+                Some(body) => run_list(body, history),
+                None => 0,
             }
-            filtered_tokens.push(token.clone());
-            filtered_quotes.push(*quote);
-            j += 1;
         }
-        let tokens = filtered_tokens;
-        let quotes = filtered_quotes;
-        if tokens.is_empty() { return; }
-        let shell_like_builtins = ["echo", "type", "pwd", "cd", "exit", "history"];
-        if shell_like_builtins.contains(&tokens[0].as_str()) {
-            // Handle >, 1>, >>, 1>>, 2>, 2>> for single builtins
-            let orig_stdout: Option<RawFd> = Some(dup2(1, 2000).unwrap());
-            let orig_stderr: Option<RawFd> = Some(dup2(2, 3000).unwrap());
-            // Handle >, 1>, >>, 1>>
-            if let Some((filename, append)) = &stdout_file {
-                use std::os::unix::io::AsRawFd;
-                let file = if *append {
-                    std::fs::OpenOptions::new().create(true).append(true).open(filename)
-                } else {
-                    std::fs::File::create(filename)
-                };
-                if let Ok(f) = file {
-                    dup2(f.as_raw_fd(), 1).ok();
-                }
-            }
-            // Handle 2> or 2>>
-            if let Some((filename, append)) = &stderr_file {
-                use std::os::unix::io::AsRawFd;
-                let file = if *append {
-                    std::fs::OpenOptions::new().create(true).append(true).open(filename)
-                } else {
-                    std::fs::File::create(filename)
+        CompoundCommand::Loop { until, condition, body } => {
+            run_loop(history, |_| Some((run_condition(condition, history) == 0) != *until), body)
+        }
+        CompoundCommand::For { name, words, body } => {
+            let values = match words {
+                Some(words) => match expand_words(words) {
+                    Ok(values) => values,
+                    Err(e) => return expansion_error(&e),
+                },
+                None => vars::positional(),
+            };
+            let mut values = values.into_iter();
+            run_loop(history, |_| {
+                let Some(value) = values.next() else {
+                    return Some(false);
                 };
-                if let Ok(f) = file {
-                    dup2(f.as_raw_fd(), 2).ok();
-                }
-            }
-            run_builtin(tokens, history);
-            std::io::stdout().flush().ok();
-            // Restore fds
-            if let Some(fd) = orig_stdout { dup2(fd, 1).ok(); if fd != 0 && fd != 1 { close(fd).ok(); } }
-            if let Some(fd) = orig_stderr { dup2(fd, 2).ok(); if fd != 0 && fd != 1 && fd != 2 { close(fd).ok(); } }
-        } else {
-            // Check if command exists in PATH
-            let cmd = tokens[0].trim();
-            let mut found = false;
-            let mut exec_path = None;
-            if cmd.contains('/') {
-                if let Ok(metadata) = std::fs::metadata(cmd) {
-                    if metadata.is_file() && metadata.permissions().mode() & 0o111 != 0 {
-                        found = true;
-                        exec_path = Some(cmd.to_string());
+                match vars::set(name, &value) {
+                    Ok(()) => Some(true),
+                    Err(e) => {
+                        eprintln!("{}", e);
+                        None
                     }
                 }
-            } else if let Ok(path_var) = env::var("PATH") {
-                for dir in path_var.split(':') {
-                    let path = std::path::Path::new(dir).join(cmd);
-                    if let Ok(metadata) = std::fs::metadata(&path) {
-                        if metadata.is_file() && metadata.permissions().mode() & 0o111 != 0 {
-                            found = true;
-                            exec_path = Some(path.to_string_lossy().to_string());
-                            break;
-                        }
-                    }
+            }, body)
+        }
+        CompoundCommand::ArithFor { init, condition, step, body } => {
+            let evaluate = |expr: &str| -> Option<i64> {
+                if expr.trim().is_empty() {
+                    return Some(1);
                 }
-                // Fallback: try unescaping single quotes if not found
-                if !found && cmd.contains("'") {
-                    let unquoted = cmd.replace("\\'", "'");
-                    for dir in path_var.split(':') {
-                        let path = std::path::Path::new(dir).join(&unquoted);
-                        if let Ok(metadata) = std::fs::metadata(&path) {
-                            if metadata.is_file() && metadata.permissions().mode() & 0o111 != 0 {
-                                found = true;
-                                exec_path = Some(path.to_string_lossy().to_string());
-                                break;
-                            }
-                        }
+                match expand_text(expr).and_then(|expr| arith::evaluate(&expr)) {
+                    Ok(value) => Some(value),
+                    Err(e) if expand::take_unset_error() => {
+                        fail_expansion(&e, true);
+                        None
                     }
-                }
-                // Fallback: try removing all backslashes if still not found
-                if !found && cmd.contains("\\") {
-                    let no_backslashes = cmd.replace("\\", "");
-                    for dir in path_var.split(':') {
-                        let path = std::path::Path::new(dir).join(&no_backslashes);
-                        if let Ok(metadata) = std::fs::metadata(&path) {
-                            if metadata.is_file() && metadata.permissions().mode() & 0o111 != 0 {
-                                found = true;
-                                exec_path = Some(path.to_string_lossy().to_string());
-                                break;
-                            }
-                        }
+                    Err(e) => {
+                        eprintln!("((: {}", e);
+                        None
                     }
                 }
-                // Fallback: try removing all single quotes if still not found
-                if !found && cmd.contains("'") {
-                    let no_single_quotes = cmd.replace("'", "");
-                    for dir in path_var.split(':') {
-                        let path = std::path::Path::new(dir).join(&no_single_quotes);
-                        if let Ok(metadata) = std::fs::metadata(&path) {
-                            if metadata.is_file() && metadata.permissions().mode() & 0o111 != 0 {
-                                found = true;
-                                exec_path = Some(path.to_string_lossy().to_string());
-                                break;
-                            }
-                        }
-                    }
+            };
+            if evaluate(init).is_none() {
+                return 1;
+            }
+            run_loop(history, |first| {
+                if !first {
+                    evaluate(step)?;
                 }
-                // Final fallback: try removing both all backslashes and all single quotes
-                if !found && (cmd.contains("'") || cmd.contains("\\")) {
-                    let no_both = cmd.replace("'", "").replace("\\", "");
-                    for dir in path_var.split(':') {
-                        let path = std::path::Path::new(dir).join(&no_both);
-                        if let Ok(metadata) = std::fs::metadata(&path) {
-                            if metadata.is_file() && metadata.permissions().mode() & 0o111 != 0 {
-                                found = true;
-                                exec_path = Some(path.to_string_lossy().to_string());
+                Some(evaluate(condition)? != 0)
+            }, body)
+        }
+        CompoundCommand::Case { word, arms } => {
+            let word = match expand_word(word) {
+                Ok(word) => word,
+                Err(e) => return expansion_error(&e),
+            };
+            let mut status = 0;
+            let mut fall_through = false;
+            for arm in arms {
+                if !fall_through {
+                    let mut matched = false;
+                    for pattern in &arm.patterns {
+                        match expand_pattern(pattern) {
+                            Ok(pattern) if pattern::matches(&pattern, &word) => {
+                                matched = true;
                                 break;
                             }
+                            Ok(_) => {}
+                            Err(e) => return expansion_error(&e),
                         }
                     }
-                }
-                // Absolute last fallback: try the original token as-is (with all backslashes and single quotes)
-                if !found {
-                    for dir in path_var.split(':') {
-                        let path = std::path::Path::new(dir).join(tokens[0].as_str());
-                        if let Ok(metadata) = std::fs::metadata(&path) {
-                            if metadata.is_file() && metadata.permissions().mode() & 0o111 != 0 {
-                                found = true;
-                                exec_path = Some(path.to_string_lossy().to_string());
-                                break;
-                            }
-                        }
+                    if !matched {
+                        continue;
                     }
                 }
-                // Try a variant with literal backslashes before each single quote
-                if !found && cmd.contains("'") {
-                    let with_escaped_single_quotes = cmd.replace("'", "\\'");
-                    for dir in path_var.split(':') {
-                        let path = std::path::Path::new(dir).join(&with_escaped_single_quotes);
-                        if let Ok(metadata) = std::fs::metadata(&path) {
-                            if metadata.is_file() && metadata.permissions().mode() & 0o111 != 0 {
-                                found = true;
-                                exec_path = Some(path.to_string_lossy().to_string());
-                                break;
-                            }
-                        }
-                    }
+                status = run_list(&arm.body, history);
+                match arm.terminator {
+                    CaseTerminator::Break => break,
+                    CaseTerminator::FallThrough => fall_through = true,
+                    CaseTerminator::Continue => fall_through = false,
                 }
             }
-            if !found {
-                println!("{}: command not found", cmd);
-                return;
+            status
+        }
+    }
+}
+
+/// Runs an `if`, `while` or `until` condition, during which `set -e` is
+/// suspended.
+fn run_condition(condition: &CommandList, history: &Arc<Mutex<Vec<String>>>) -> i32 {
+    CONDITION_DEPTH.fetch_add(1, Ordering::Relaxed);
+    let status = run_list(condition, history);
+    CONDITION_DEPTH.fetch_sub(1, Ordering::Relaxed);
+    status
+}
+
+/// Runs `body` for as long as `next` returns `Some(true)`, handling `break`
+/// and `continue`. `next` is told whether this is the first iteration;
+/// `None` ends the loop with a failure status. Returns the status of the
+/// last iteration, or 0 if the body never ran.
+fn run_loop(
+    history: &Arc<Mutex<Vec<String>>>,
+    mut next: impl FnMut(bool) -> Option<bool>,
+    body: &CommandList,
+) -> i32 {
+    LOOP_DEPTH.fetch_add(1, Ordering::Relaxed);
+    let mut status = 0;
+    let mut first = true;
+    loop {
+        let proceed = next(first);
+        first = false;
+        match flow() {
+            Flow::Break(n) => {
+                set_flow(if n > 1 { Flow::Break(n - 1) } else { Flow::Normal });
+                break;
+            }
+            Flow::Continue(n) if n > 1 => {
+                set_flow(Flow::Continue(n - 1));
+                break;
             }
-            let (stderr_r, stderr_w) = nix_pipe().unwrap();
-            match unsafe { fork() } {
-                Ok(ForkResult::Child) => {
-                    // Handle >, 1>, >>, 1>>
-                    if let Some((filename, append)) = &stdout_file {
-                        use std::os::unix::io::AsRawFd;
-                        let file = if *append {
-                            std::fs::OpenOptions::new().create(true).append(true).open(filename)
-                        } else {
-                            std::fs::File::create(filename)
-                        };
-                        if let Ok(f) = file {
-                            dup2(f.as_raw_fd(), 1).ok();
-                        }
-                    }
-                    // Handle 2> or 2>>
-                    if let Some((filename, append)) = &stderr_file {
-                        use std::os::unix::io::AsRawFd;
-                        let file = if *append {
-                            std::fs::OpenOptions::new().create(true).append(true).open(filename)
-                        } else {
-                            std::fs::File::create(filename)
-                        };
-                        if let Ok(f) = file {
-                            dup2(f.as_raw_fd(), 2).ok();
-                        }
-                    } else {
-                        dup2(stderr_w, 2).ok();
-                    }
-                    close(stderr_r).ok();
-                    close(stderr_w).ok();
-                    let exec_cmd = exec_path.unwrap_or_else(|| tokens[0].trim().to_string());
-                    let cmd = CString::new(exec_cmd.clone()).unwrap();
-                    let args: Vec<CString> = std::iter::once(tokens[0].clone())
-                        .chain(tokens.iter().zip(quotes.iter()).skip(1).map(|(s, q)| {
-                            match q {
-                                QuoteType::Single | QuoteType::Double => {
-                                    if !s.starts_with('-') && !std::path::Path::new(s).exists() {
-                                        let quoted = format!("'{}'", s);
-                                        let with_1_backslash = format!("{}\\", s);
-                                        let quoted_with_1_backslash = format!("'{}\\'", s);
-                                        let with_2_backslashes = format!("{}\\\\", s);
-                                        let quoted_with_2_backslashes = format!("'{}\\\\'", s);
-                                        let variants = [
-                                            &quoted,
-                                            &with_1_backslash,
-                                            &quoted_with_1_backslash,
-                                            &with_2_backslashes,
-                                            &quoted_with_2_backslashes,
-                                        ];
-                                        for v in variants.iter() {
-                                            if std::path::Path::new(v).exists() {
-                                                return (*v).clone();
-                                            }
-                                        }
-                                        // Aggressive fallback: scan parent dir for substring match
-                                        if let Some(parent) = std::path::Path::new(s).parent() {
-                                            if let Ok(entries) = std::fs::read_dir(parent) {
-                                                for entry in entries.flatten() {
-                                                    let fname = entry.file_name().to_string_lossy().to_string();
-                                                    if fname.contains(s) || fname.contains(&quoted) {
-                                                        return entry.path().to_string_lossy().to_string();
-                                                    }
-                                                }
-                                            }
-                                        }
-                                        // Extreme fallback: fuzzy match by edit distance
-                                        if !s.starts_with('-') && !std::path::Path::new(s).exists() {
-                                            let quoted = format!("'{}'", s);
-                                            let mut best_match = None;
-                                            let mut best_dist = usize::MAX;
-                                            if let Some(parent) = std::path::Path::new(s).parent() {
-                                                if let Ok(entries) = std::fs::read_dir(parent) {
-                                                    for entry in entries.flatten() {
-                                                        let fname = entry.file_name().to_string_lossy().to_string();
-                                                        let d1 = levenshtein(&fname, s);
-                                                        let d2 = levenshtein(&fname, &quoted);
-                                                        let d = d1.min(d2);
-                                                        if d < best_dist {
-                                                            best_dist = d;
-                                                            best_match = Some(entry.path().to_string_lossy().to_string());
-                                                        }
-                                                    }
-                                                }
-                                            }
-                                            if let Some(m) = best_match {
-                                                return m;
-                                            }
-                                        }
-                                    }
-                                    s.clone()
-                                },
-                                QuoteType::None => unescape_backslashes(s),
-                            }
-                        }))
-                        .map(|s| CString::new(s).unwrap())
-                        .collect();
-                    execvp(&cmd, &args).unwrap_or_else(|_| { unsafe { libc::_exit(127) } });
-                }
-                Ok(ForkResult::Parent { child }) => {
-                    close(stderr_w).ok();
-                    let _ = waitpid(child, None);
-                    let file = unsafe { File::from_raw_fd(stderr_r) };
-                    let reader = BufReader::new(file);
-                    for line in reader.lines().flatten() {
-                        if !line.contains("write error: Broken pipe") {
-                            let _ = writeln_ignore_broken_pipe(std::io::stderr(), &line);
-                        }
-                    }
-                }
-                Err(_) => { eprintln!("fork failed"); return; }
+            Flow::Continue(_) => {
+                set_flow(Flow::Normal);
+                continue;
+            }
+            Flow::Return => break,
+            Flow::Normal => {}
+        }
+        match proceed {
+            Some(true) => {}
+            Some(false) => break,
+            None => {
+                status = 1;
+                break;
             }
         }
+        if signals::interrupted() {
+            break;
+        }
+        status = run_list(body, history);
+        match flow() {
+            Flow::Break(n) => {
+                set_flow(if n > 1 { Flow::Break(n - 1) } else { Flow::Normal });
+                break;
+            }
+            Flow::Continue(n) if n > 1 => {
+                set_flow(Flow::Continue(n - 1));
+                break;
+            }
+            Flow::Continue(_) => set_flow(Flow::Normal),
+            Flow::Return => break,
+            Flow::Normal => {}
+        }
+        if signals::interrupted() {
+            break;
+        }
+    }
+    LOOP_DEPTH.fetch_sub(1, Ordering::Relaxed);
+    if signals::interrupted() {
+        return 130;
+    }
+    status
+}
+
+/// Calls a shell function with `args[1..]` as its positional parameters.
+/// Loops around the call cannot be broken out of from inside it.
+fn call_function(body: &Command, args: &[String], history: &Arc<Mutex<Vec<String>>>) -> i32 {
+    let Command::Compound { command, redirects } = body else {
+        return 0;
+    };
+    let saved_args = vars::positional();
+    vars::set_positional(args[1..].to_vec());
+    vars::push_frame();
+    let loop_depth = LOOP_DEPTH.swap(0, Ordering::Relaxed);
+    let mut status = run_compound_redirected(command, redirects, history);
+    if flow() == Flow::Return {
+        set_flow(Flow::Normal);
+        status = last_status();
     }
+    LOOP_DEPTH.store(loop_depth, Ordering::Relaxed);
+    vars::pop_frame();
+    vars::set_positional(saved_args);
+    status
+}
+
+/// Runs the commands of a sourced file through `run`. A `return` in the
+/// file ends it there, with the status it gives.
+pub fn run_sourced(run: impl FnOnce() -> i32) -> i32 {
+    SOURCE_DEPTH.fetch_add(1, Ordering::Relaxed);
+    let mut status = run();
+    SOURCE_DEPTH.fetch_sub(1, Ordering::Relaxed);
+    if flow() == Flow::Return {
+        set_flow(Flow::Normal);
+        status = last_status();
+    }
+    status
+}
+
+/// Whether a `return` is unwinding, so that no more commands should run.
+pub fn returning() -> bool {
+    flow() == Flow::Return
 }
 
-fn levenshtein(a: &str, b: &str) -> usize {
-    let mut costs = vec![0; b.len() + 1];
-    for j in 0..=b.len() {
-        costs[j] = j;
+/// The `return` builtin: ends the innermost function call or sourced file
+/// with status `n`, or with the last command's status.
+pub fn builtin_return(args: &[String]) -> i32 {
+    if !vars::in_function() && SOURCE_DEPTH.load(Ordering::Relaxed) == 0 {
+        eprintln!("return: can only `return' from a function or sourced script");
+        return 2;
     }
-    for (i, ca) in a.chars().enumerate() {
-        let mut last = i;
-        costs[0] = i + 1;
-        for (j, cb) in b.chars().enumerate() {
-            let old = costs[j + 1];
-            costs[j + 1] = std::cmp::min(
-                std::cmp::min(costs[j] + 1, costs[j + 1] + 1),
-                last + if ca == cb { 0 } else { 1 },
-            );
-            last = old;
+    let status = match args.first() {
+        None => last_status(),
+        Some(arg) => match arg.parse::<i64>() {
+            Ok(n) => n.rem_euclid(256) as i32,
+            Err(_) => {
+                eprintln!("return: {}: numeric argument required", arg);
+                2
+            }
+        },
+    };
+    set_last_status(status);
+    set_flow(Flow::Return);
+    status
+}
+
+/// The `break` and `continue` builtins: leave, or skip to the next
+/// iteration of, the innermost `n` enclosing loops.
+pub fn builtin_break(name: &str, args: &[String]) -> i32 {
+    let depth = LOOP_DEPTH.load(Ordering::Relaxed);
+    let count = match args.first() {
+        None => 1,
+        Some(arg) => match arg.parse::<i64>() {
+            Ok(n) if n >= 1 => n as usize,
+            Ok(_) => {
+                eprintln!("{}: {}: loop count out of range", name, arg);
+                return 1;
+            }
+            Err(_) => {
+                eprintln!("{}: {}: numeric argument required", name, arg);
+                return 128;
+            }
+        },
+    };
+    if depth == 0 {
+        eprintln!("{}: only meaningful in a `for', `while', or `until' loop", name);
+        return 0;
+    }
+    let count = count.min(depth);
+    set_flow(if name == "break" { Flow::Break(count) } else { Flow::Continue(count) });
+    0
+}
+
+/// Runs `((expr))`: true if the expression evaluates to non-zero.
+fn run_arithmetic(expr: &str) -> i32 {
+    let expr = match expand_text(expr) {
+        Ok(expr) => expr,
+        Err(e) => return expansion_error(&e),
+    };
+    trace_line(&format!("(( {} ))", expr.trim()));
+    match arith::evaluate(&expr) {
+        Ok(value) => (value == 0) as i32,
+        // Under `set -u`, an unset variable is an expansion error.
+        Err(e) if expand::take_unset_error() => fail_expansion(&e, true),
+        Err(e) => {
+            eprintln!("((: {}", e);
+            1
         }
     }
-    costs[b.len()]
-} 
\ No newline at end of file
+}
+
+/// Runs an expanded command inside a forked child: applies its
+/// redirections, then runs a builtin or replaces the process with the
+/// program. Returns the status the child should exit with.
+fn exec_expanded(command: Expanded, history: &Arc<Mutex<Vec<String>>>) -> i32 {
+    let Expanded { assignments, args, redirects } = command;
+    if let Err(e) = redirect::apply(&redirects).and_then(|_| vars::export_assignments(&assignments)) {
+        eprintln!("{}", e);
+        return 1;
+    }
+    if args.is_empty() {
+        return 0;
+    }
+    if let Some(body) = functions::get(&args[0]) {
+        return call_function(&body, &args, history);
+    }
+    if BUILTINS.contains(&args[0].as_str()) {
+        return run_builtin(args, history);
+    }
+    let Some(path) = find_executable(&args[0]) else {
+        eprintln!("{}: command not found", args[0]);
+        return 127;
+    };
+    let to_cstring = |s: &str| CString::new(s).unwrap_or_default();
+    let argv: Vec<CString> = args.iter().map(|a| to_cstring(a)).collect();
+    let err = execv(&to_cstring(&path), &argv).unwrap_err();
+    eprintln!("{}: {}", args[0], err.desc());
+    match err {
+        Errno::ENOENT => 127,
+        _ => 126,
+    }
+}
+
+/// Resolves a command name against `$PATH`. A name containing a slash is
+/// used as is.
+fn find_executable(name: &str) -> Option<String> {
+    if name.contains('/') {
+        return Some(name.to_string());
+    }
+    let path = env::var("PATH").ok()?;
+    path.split(':')
+        .map(|dir| std::path::Path::new(if dir.is_empty() { "." } else { dir }).join(name))
+        .find(|candidate| {
+            std::fs::metadata(candidate)
+                .map(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+                .unwrap_or(false)
+        })
+        .map(|candidate| candidate.to_string_lossy().into_owned())
+}
+
+/// Runs a function or builtin in the shell process with its redirections
+/// applied, restoring the original descriptors afterwards.
+fn run_redirected_builtin(command: &Expanded, history: &Arc<Mutex<Vec<String>>>) -> i32 {
+    let saved = SavedFds::save(command.redirects.iter().map(Redirect::fd));
+    let status = match redirect::apply(&command.redirects) {
+        Ok(()) => vars::with_assignments(&command.assignments, || match functions::get(&command.args[0]) {
+            Some(body) => call_function(&body, &command.args, history),
+            None => run_builtin(command.args.clone(), history),
+        })
+            .unwrap_or_else(|e| { eprintln!("{}", e); 1 }),
+        Err(e) => { eprintln!("{}", e); 1 }
+    };
+    saved.restore();
+    status
+}
+
+/// Performs the redirections of a command that has no words, such as
+/// `> file`, which creates or truncates the file.
+fn apply_redirects_only(redirects: &[Redirect]) -> i32 {
+    let saved = SavedFds::save(redirects.iter().map(Redirect::fd));
+    let result = redirect::apply(redirects);
+    saved.restore();
+    match result {
+        Ok(()) => 0,
+        Err(e) => { eprintln!("{}", e); 1 }
+    }
+}
+
+/// Expands the values of `NAME=value` command prefixes.
+fn expand_assignments(assignments: &[(String, Word)]) -> Result<Vec<(String, String)>, String> {
+    assignments.iter()
+        .map(|(name, value)| Ok((name.clone(), expand_assignment(value)?)))
+        .collect()
+}
+
+/// Performs a command consisting only of assignments, setting each shell
+/// variable in order so later values can refer to earlier ones.
+fn assign_variables(assignments: &[(String, Word)]) -> i32 {
+    expand::take_substitution_status();
+    for (name, value) in assignments {
+        let value = match expand_assignment(value) {
+            Ok(value) => value,
+            Err(e) => return expansion_error(&e),
+        };
+        trace(&[(name.clone(), value.clone())], &[]);
+        if let Err(e) = vars::set(name, &value) {
+            eprintln!("{}", e);
+            return 1;
+        }
+    }
+    expand::take_substitution_status().unwrap_or(0)
+}
+
+/// Reports a failed expansion. A non-interactive shell exits, as POSIX
+/// requires for expansion errors.
+fn expansion_error(e: &str) -> i32 {
+    fail_expansion(e, expand::take_unset_error())
+}
+
+/// Reports an expansion error, from which a non-interactive shell exits
+/// with 127 if it was an unset parameter, and otherwise with 1, as for
+/// bad substitutions and arithmetic errors.
+fn fail_expansion(e: &str, unset: bool) -> i32 {
+    eprintln!("{}", e);
+    if !options::is_interactive() {
+        std::io::stdout().flush().ok();
+        std::process::exit(if unset { 127 } else { 1 });
+    }
+    1
+}
+
+/// Prints a command to stderr under `set -x`, prefixed with `$PS4`.
+fn trace(assignments: &[(String, String)], words: &[String]) {
+    let words: Vec<String> = assignments.iter()
+        .map(|(name, value)| format!("{}={}", name, shell_quote(value)))
+        .chain(words.iter().map(|w| shell_quote(w)))
+        .collect();
+    trace_line(&words.join(" "));
+}
+
+/// Writes `line` to stderr after `$PS4` when `set -x` is on.
+fn trace_line(line: &str) {
+    if !options::option_enabled("xtrace") {
+        return;
+    }
+    let ps4 = vars::get("PS4").unwrap_or_else(|| "+ ".to_string());
+    let ps4 = expand_text(&ps4).unwrap_or(ps4);
+    eprintln!("{}{}", ps4, line);
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Runs `text` in the shell. `$?`, the loop and function state and the
+    /// local variable frames are global, so tests take turns.
+    fn run(text: &str) -> i32 {
+        static SHELL: Mutex<()> = Mutex::new(());
+        let _turn = SHELL.lock().unwrap_or_else(|e| e.into_inner());
+        execute_list(text, &Arc::new(Mutex::new(Vec::new())))
+    }
+
+    #[test]
+    fn for_loop_status() {
+        assert_eq!(run("false; for PIPELINE_TEST_X in a b; do PIPELINE_TEST_Y=$PIPELINE_TEST_X; done"), 0);
+        assert_eq!(vars::get("PIPELINE_TEST_Y").as_deref(), Some("b"));
+        assert_eq!(run("false; for PIPELINE_TEST_X in; do false; done"), 0);
+        assert_eq!(run("for PIPELINE_TEST_X in a; do false; done"), 1);
+        vars::set("PIPELINE_TEST_RO", "x").unwrap();
+        vars::set_readonly("PIPELINE_TEST_RO");
+        assert_eq!(run("for PIPELINE_TEST_RO in a; do :; done"), 1);
+    }
+
+    #[test]
+    fn colon_true_and_false_drive_loops() {
+        assert_eq!(run("PIPELINE_TEST_N=0; while :; do PIPELINE_TEST_N=$((PIPELINE_TEST_N + 1)); \
+            if ((PIPELINE_TEST_N == 3)); then break; fi; done"), 0);
+        assert_eq!(vars::get("PIPELINE_TEST_N").as_deref(), Some("3"));
+        assert_eq!(run("PIPELINE_TEST_M=0; until false; do PIPELINE_TEST_M=1; break; done"), 0);
+        assert_eq!(vars::get("PIPELINE_TEST_M").as_deref(), Some("1"));
+        assert_eq!(run("while false; do :; done"), 0);
+        assert_eq!(run("true && false || :"), 0);
+    }
+
+    #[test]
+    fn functions_with_locals_and_return() {
+        assert_eq!(run("pipeline_test_f() { local PIPELINE_TEST_L=in; PIPELINE_TEST_G=$1:$PIPELINE_TEST_L; return 300; }; \
+            PIPELINE_TEST_L=out; pipeline_test_f arg"), 44);
+        assert_eq!(vars::get("PIPELINE_TEST_L").as_deref(), Some("out"));
+        assert_eq!(vars::get("PIPELINE_TEST_G").as_deref(), Some("arg:in"));
+        // Locals are visible to the functions called while they are in scope.
+        assert_eq!(run("pipeline_test_inner() { PIPELINE_TEST_SEEN=$PIPELINE_TEST_V; }; \
+            pipeline_test_outer() { local PIPELINE_TEST_V=local; pipeline_test_inner; }; pipeline_test_outer"), 0);
+        assert_eq!(vars::get("PIPELINE_TEST_SEEN").as_deref(), Some("local"));
+        assert_eq!(run("pipeline_test_r() { return; }; false; pipeline_test_r"), 1);
+        assert_eq!(run("pipeline_test_r() { false; return 0; false; }; pipeline_test_r"), 0);
+        assert_eq!(run("local PIPELINE_TEST_NOT_LOCAL=1"), 1);
+        assert_eq!(run("return"), 2);
+    }
+
+    #[test]
+    fn source_runs_a_file_in_the_current_shell() {
+        let path = std::env::temp_dir().join(format!("source_test_{}.sh", std::process::id()));
+        std::fs::write(&path, "PIPELINE_TEST_SOURCED=$1:$#\nreturn 5\nPIPELINE_TEST_SOURCED=late\n").unwrap();
+        let file = path.to_str().unwrap();
+        assert_eq!(run(&format!("source {} a b", file)), 5);
+        assert_eq!(vars::get("PIPELINE_TEST_SOURCED").as_deref(), Some("a:2"));
+        assert_eq!(run(&format!(". {}", file)), 5);
+        assert_eq!(vars::get("PIPELINE_TEST_SOURCED").as_deref(), Some(":0"));
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(run(&format!("source {}", file)), 1);
+        assert_eq!(run("source"), 2);
+    }
+
+    #[test]
+    fn brace_groups_run_in_the_current_shell() {
+        assert_eq!(run("{ PIPELINE_TEST_BRACE=1; false; }"), 1);
+        assert_eq!(vars::get("PIPELINE_TEST_BRACE").as_deref(), Some("1"));
+        assert_eq!(run("{ false; } || { PIPELINE_TEST_BRACE=2; }"), 0);
+        assert_eq!(vars::get("PIPELINE_TEST_BRACE").as_deref(), Some("2"));
+    }
+}
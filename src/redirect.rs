@@ -0,0 +1,216 @@
+// redirect.rs
+
+use crate::ast::{RedirectOp, Redirection};
+use crate::expand;
+use nix::fcntl::{fcntl, open, FcntlArg, OFlag};
+use nix::sys::stat::Mode;
+use nix::unistd::{close, dup2, mkstemp};
+use std::io::{Seek, SeekFrom, Write};
+use std::os::unix::io::{FromRawFd, IntoRawFd, RawFd};
+
+/// One redirection of a command, applied in order to the descriptor table of
+/// the process that runs it.
+#[derive(Debug)]
+pub enum Redirect {
+    /// `N<file`, `N>file`, `N>>file`, `N<>file`.
+    Open { fd: RawFd, path: String, flags: OFlag },
+    /// `N>&M` / `N<&M`: make `fd` a copy of descriptor `source`.
+    Dup { fd: RawFd, source: String },
+    /// `N>&-` / `N<&-`.
+    Close { fd: RawFd },
+    /// Here-documents and here-strings: `fd` reads `data`.
+    Data { fd: RawFd, data: String },
+}
+
+impl Redirect {
+    /// The descriptor this redirection replaces.
+    pub fn fd(&self) -> RawFd {
+        match *self {
+            Redirect::Open { fd, .. } | Redirect::Dup { fd, .. } | Redirect::Close { fd } | Redirect::Data { fd, .. } => fd,
+        }
+    }
+}
+
+/// Expands the targets of a command's redirections, and turns them into the
+/// descriptor operations to perform.
+pub fn expand(redirections: &[Redirection]) -> Result<Vec<Redirect>, String> {
+    let mut redirects = Vec::new();
+    for redirection in redirections {
+        let target = match redirection.op {
+            RedirectOp::HereDoc | RedirectOp::HereString => expand::expand_word(&redirection.target)?,
+            _ => {
+                let mut fields = expand::expand_words(std::slice::from_ref(&redirection.target))?;
+                if fields.len() != 1 {
+                    return Err(format!("{}: ambiguous redirect", redirection.target.text));
+                }
+                fields.remove(0)
+            }
+        };
+        let fd = redirection.fd;
+        let write = OFlag::O_WRONLY | OFlag::O_CREAT;
+        let open = |fd: RawFd, flags: OFlag| Redirect::Open { fd, path: target.clone(), flags };
+        match redirection.op {
+            RedirectOp::Input => redirects.push(open(fd.unwrap_or(0), OFlag::O_RDONLY)),
+            RedirectOp::Output | RedirectOp::Clobber => redirects.push(open(fd.unwrap_or(1), write | OFlag::O_TRUNC)),
+            RedirectOp::Append => redirects.push(open(fd.unwrap_or(1), write | OFlag::O_APPEND)),
+            RedirectOp::ReadWrite => redirects.push(open(fd.unwrap_or(0), OFlag::O_RDWR | OFlag::O_CREAT)),
+            RedirectOp::HereDoc => redirects.push(Redirect::Data { fd: fd.unwrap_or(0), data: target }),
+            RedirectOp::HereString => redirects.push(Redirect::Data { fd: fd.unwrap_or(0), data: target + "\n" }),
+            // `>&file` without a descriptor number means the same as `&>file`.
+            RedirectOp::DupOutput if fd.is_none() && target != "-" && !target.bytes().all(|b| b.is_ascii_digit()) => {
+                redirects.push(open(1, write | OFlag::O_TRUNC));
+                redirects.push(Redirect::Dup { fd: 2, source: "1".to_string() });
+            }
+            RedirectOp::OutputAll | RedirectOp::AppendAll => {
+                let mode = if redirection.op == RedirectOp::OutputAll { OFlag::O_TRUNC } else { OFlag::O_APPEND };
+                redirects.push(open(1, write | mode));
+                redirects.push(Redirect::Dup { fd: 2, source: "1".to_string() });
+            }
+            RedirectOp::DupInput | RedirectOp::DupOutput => {
+                let fd = fd.unwrap_or(if redirection.op == RedirectOp::DupInput { 0 } else { 1 });
+                if target == "-" {
+                    redirects.push(Redirect::Close { fd });
+                } else {
+                    redirects.push(Redirect::Dup { fd, source: target });
+                }
+            }
+        }
+    }
+    Ok(redirects)
+}
+
+/// Applies redirections left to right to the current process.
+pub fn apply(redirects: &[Redirect]) -> Result<(), String> {
+    for redirect in redirects {
+        match redirect {
+            Redirect::Open { fd, path, flags } => {
+                let file = open(path.as_str(), *flags, Mode::from_bits_truncate(0o666))
+                    .map_err(|e| format!("{}: {}", path, e.desc()))?;
+                move_fd(file, *fd);
+            }
+            Redirect::Dup { fd, source } => {
+                let source: RawFd = source.parse().map_err(|_| format!("{}: ambiguous redirect", source))?;
+                if source != *fd {
+                    dup2(source, *fd).map_err(|_| format!("{}: Bad file descriptor", source))?;
+                }
+            }
+            Redirect::Close { fd } => {
+                close(*fd).ok();
+            }
+            Redirect::Data { fd, data } => move_fd(data_fd(data)?, *fd),
+        }
+    }
+    Ok(())
+}
+
+fn move_fd(from: RawFd, to: RawFd) {
+    if from != to {
+        dup2(from, to).ok();
+        close(from).ok();
+    }
+}
+
+/// Opens a descriptor that reads `data`, through an unlinked temporary file
+/// so that here-documents of any size work without a writer process. The
+/// file gets a fresh unpredictable name and is created exclusively, so it
+/// cannot be swapped for a symlink planted in the temporary directory.
+fn data_fd(data: &str) -> Result<RawFd, String> {
+    let error = |e: &dyn std::fmt::Display| format!("cannot create temp file for here-document: {}", e);
+    let template = std::env::temp_dir().join("rustshell-here-XXXXXX");
+    let (fd, path) = mkstemp(&template).map_err(|e| error(&e.desc()))?;
+    let _ = std::fs::remove_file(&path);
+    let mut file = unsafe { std::fs::File::from_raw_fd(fd) };
+    file.write_all(data.as_bytes())
+        .and_then(|_| file.seek(SeekFrom::Start(0)))
+        .map_err(|e| error(&e))?;
+    Ok(file.into_raw_fd())
+}
+
+/// Descriptors displaced by redirections applied in the shell process
+/// itself, so that they can be put back once a builtin returns.
+pub struct SavedFds(Vec<(RawFd, Option<RawFd>)>);
+
+impl SavedFds {
+    pub fn save(fds: impl IntoIterator<Item = RawFd>) -> SavedFds {
+        let mut saved: Vec<(RawFd, Option<RawFd>)> = Vec::new();
+        for fd in fds {
+            if saved.iter().all(|&(s, _)| s != fd) {
+                saved.push((fd, fcntl(fd, FcntlArg::F_DUPFD_CLOEXEC(10)).ok()));
+            }
+        }
+        SavedFds(saved)
+    }
+
+    pub fn restore(self) {
+        std::io::stdout().flush().ok();
+        for (fd, copy) in self.0.into_iter().rev() {
+            match copy {
+                Some(copy) => move_fd(copy, fd),
+                None => { close(fd).ok(); }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::Command;
+    use crate::parser::parse_command_list;
+    use crate::vars;
+    use std::io::Read;
+
+    fn redirects(text: &str) -> Result<Vec<Redirect>, String> {
+        let list = parse_command_list(text).unwrap();
+        let Command::Simple(command) = &list.and_ors[0].pipelines[0].1.commands[0] else {
+            panic!("not a simple command");
+        };
+        expand(&command.redirects)
+    }
+
+    fn describe(text: &str) -> Vec<String> {
+        redirects(text).unwrap().iter().map(|r| match r {
+            Redirect::Open { fd, path, flags } => {
+                let mode = if flags.contains(OFlag::O_RDWR) {
+                    "<>"
+                } else if flags.contains(OFlag::O_APPEND) {
+                    ">>"
+                } else if flags.contains(OFlag::O_WRONLY) {
+                    ">"
+                } else {
+                    "<"
+                };
+                format!("{}{}{}", fd, mode, path)
+            }
+            Redirect::Dup { fd, source } => format!("{}>&{}", fd, source),
+            Redirect::Close { fd } => format!("{}>&-", fd),
+            Redirect::Data { fd, data } => format!("{}<<{:?}", fd, data),
+        }).collect()
+    }
+
+    #[test]
+    fn operators_and_default_descriptors() {
+        assert_eq!(describe("cmd <in >out 2>>log"), ["0<in", "1>out", "2>>log"]);
+        assert_eq!(describe("cmd 3<>f 2>&1 4<&-"), ["3<>f", "2>&1", "4>&-"]);
+        assert_eq!(describe("cmd &>f"), ["1>f", "2>&1"]);
+        assert_eq!(describe("cmd &>>f"), ["1>>f", "2>&1"]);
+        assert_eq!(describe("cmd >&f"), ["1>f", "2>&1"]);
+        assert_eq!(describe("cmd >&2"), ["1>&2"]);
+        assert_eq!(describe("cmd <<<word"), ["0<<\"word\\n\""]);
+    }
+
+    #[test]
+    fn target_must_expand_to_one_field() {
+        vars::set("REDIRECT_TEST_TWO", "a b").unwrap();
+        assert_eq!(redirects("cmd >$REDIRECT_TEST_TWO").unwrap_err(), "$REDIRECT_TEST_TWO: ambiguous redirect");
+        assert_eq!(describe("cmd >\"$REDIRECT_TEST_TWO\""), ["1>a b"]);
+    }
+
+    #[test]
+    fn data_is_read_from_the_start() {
+        let fd = data_fd("line 1\nline 2\n").unwrap();
+        let mut text = String::new();
+        unsafe { std::fs::File::from_raw_fd(fd) }.read_to_string(&mut text).unwrap();
+        assert_eq!(text, "line 1\nline 2\n");
+    }
+}
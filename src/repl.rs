@@ -1,16 +1,20 @@
 // repl.rs
 
 use rustyline::error::ReadlineError;
-use rustyline::{Editor, Helper, CompletionType, Config};
+use rustyline::{Editor, CompletionType, Config};
 use std::sync::{Arc, Mutex};
 use crate::completion::BuiltinCompleter;
-use crate::pipeline::execute_pipeline;
-use crate::history;
+use crate::parser::read_command;
+use crate::pipeline::{execute_list, set_last_status};
+use crate::signals;
 use std::io::BufRead;
 use std::io::Write;
 use crate::builtins::LAST_A_IDX;
+use crate::jobs;
+use crate::vars;
 
 pub fn start_repl() {
+    jobs::init_job_control();
     let config = Config::builder().completion_type(CompletionType::List).build();
     let completer = BuiltinCompleter::new();
     let mut rl = Editor::with_config(config).expect("Failed to create Editor");
@@ -21,7 +25,7 @@ pub fn start_repl() {
         if let Ok(file) = std::fs::File::open(&histfile) {
             let reader = std::io::BufReader::new(file);
             let mut hist = history.lock().unwrap();
-            for line in reader.lines().flatten() {
+            for line in reader.lines().map_while(Result::ok) {
                 if !line.trim().is_empty() {
                     hist.push(line);
                 }
@@ -29,6 +33,7 @@ pub fn start_repl() {
         }
     }
     loop {
+        jobs::notify();
         let readline = rl.readline("$ ");
         if let Some(helper) = rl.helper() {
             let c = helper as &BuiltinCompleter;
@@ -51,16 +56,44 @@ pub fn start_repl() {
         }
         match readline {
             Ok(line) => {
-                let _ = rl.add_history_entry(line.as_str());
-                let trimmed = line.trim();
-                if trimmed.is_empty() { continue; }
-                {
-                    let mut hist = history.lock().unwrap();
-                    hist.push(trimmed.to_string());
+                if line.trim().is_empty() { continue; }
+                // Here-document bodies and unfinished commands continue on
+                // the following lines, read with the `$PS2` prompt.
+                let ps2 = vars::get("PS2").unwrap_or_else(|| "> ".to_string());
+                let mut lines = vec![line.clone()];
+                let mut cancelled = false;
+                let command = read_command(&line, || match rl.readline(&ps2) {
+                    Ok(next) => {
+                        lines.push(next.clone());
+                        Some(next)
+                    }
+                    Err(ReadlineError::Interrupted) => {
+                        cancelled = true;
+                        None
+                    }
+                    Err(_) => None,
+                });
+                let entry = lines.join("\n");
+                let _ = rl.add_history_entry(entry.as_str());
+                history.lock().unwrap().push(entry.trim().to_string());
+                match command {
+                    _ if cancelled => set_last_status(130),
+                    Ok(command) => {
+                        execute_list(&command, &history);
+                        // Children check the flag when they start.
+                        signals::clear_interrupted();
+                    }
+                    Err(e) => {
+                        eprintln!("{}", e);
+                        set_last_status(2);
+                    }
                 }
-                execute_pipeline(&line, &history);
             }
-            Err(ReadlineError::Interrupted) | Err(ReadlineError::Eof) => {
+            Err(ReadlineError::Interrupted) => {
+                // Ctrl-C discards the line being edited, like bash.
+                set_last_status(130);
+            }
+            Err(ReadlineError::Eof) => {
                 break;
             }
             Err(err) => {
@@ -0,0 +1,98 @@
+// script.rs
+
+use std::io::BufRead;
+use std::sync::{Arc, Mutex};
+use crate::parser::read_command;
+use crate::pipeline::{self, execute_list, last_status, set_last_status};
+use crate::vars;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+
+/// Runs commands read from `input` one line at a time, without a prompt or
+/// line editing. Used for script files, `-c` strings and piped stdin.
+pub fn run_lines(input: impl BufRead) -> i32 {
+    let history = Arc::new(Mutex::new(Vec::new()));
+    let mut lines = input.lines().map_while(Result::ok);
+    while let Some(line) = lines.next() {
+        if pipeline::returning() {
+            break;
+        }
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+        match read_command(&line, || lines.next()) {
+            Ok(command) => { execute_list(&command, &history); }
+            Err(e) => {
+                eprintln!("{}", e);
+                set_last_status(2);
+            }
+        }
+    }
+    last_status()
+}
+
+/// The `source` / `.` builtin: runs a file's commands in the current shell.
+/// Extra arguments become the positional parameters while it runs.
+pub fn builtin_source(name: &str, args: &[String]) -> i32 {
+    let Some(file) = args.first() else {
+        eprintln!("{}: filename argument required", name);
+        eprintln!("{}: usage: {} filename [arguments]", name, name);
+        return 2;
+    };
+    let Some(path) = find_sourced(file) else {
+        eprintln!("{}: No such file or directory", file);
+        return 1;
+    };
+    let input = match std::fs::File::open(&path) {
+        Ok(input) => input,
+        Err(e) => {
+            eprintln!("{}: {}: {}", name, file, e);
+            return 1;
+        }
+    };
+    let saved_args = (args.len() > 1).then(|| {
+        let saved = vars::positional();
+        vars::set_positional(args[1..].to_vec());
+        saved
+    });
+    let status = pipeline::run_sourced(|| run_lines(BufReader::new(input)));
+    if let Some(saved) = saved_args {
+        vars::set_positional(saved);
+    }
+    status
+}
+
+/// A file name without a slash is looked for in `$PATH`, then in the
+/// current directory.
+fn find_sourced(file: &str) -> Option<PathBuf> {
+    if !file.contains('/') {
+        let path = vars::get("PATH").unwrap_or_default();
+        let found = path.split(':')
+            .filter(|dir| !dir.is_empty())
+            .map(|dir| Path::new(dir).join(file))
+            .find(|candidate| candidate.is_file());
+        if found.is_some() {
+            return found;
+        }
+    }
+    Some(PathBuf::from(file)).filter(|path| path.exists())
+}
+
+/// Runs the startup files: `/etc/profile` and `~/.profile` for a login
+/// shell, then `~/.rustshellrc` for an interactive one unless `rc` is false.
+/// Files that do not exist are skipped.
+pub fn load_startup_files(login: bool, rc: bool) {
+    let home = vars::get("HOME").map(PathBuf::from);
+    let mut files = Vec::new();
+    if login {
+        files.push(PathBuf::from("/etc/profile"));
+        files.extend(home.iter().map(|home| home.join(".profile")));
+    }
+    if rc {
+        files.extend(home.iter().map(|home| home.join(".rustshellrc")));
+    }
+    for file in files.into_iter().filter(|file| file.is_file()) {
+        builtin_source("source", &[file.to_string_lossy().into_owned()]);
+    }
+}
@@ -0,0 +1,58 @@
+// signals.rs
+
+use nix::sys::signal::{sigaction, signal, SaFlags, SigAction, SigHandler, SigSet, Signal};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Signals an interactive shell ignores, apart from SIGINT, which it
+/// catches. Keyboard signals are meant for the foreground job, which has its
+/// own process group; the job control ones must never stop the shell while
+/// it hands the terminal between groups.
+const INTERACTIVE_SIGNALS: &[Signal] = &[
+    Signal::SIGINT,
+    Signal::SIGQUIT,
+    Signal::SIGTSTP,
+    Signal::SIGTTIN,
+    Signal::SIGTTOU,
+];
+
+/// Set when a foreground job was killed by SIGINT, so the shell can abandon
+/// the rest of the command line as if it had been interrupted itself.
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Ignores the interactive signals, but catches SIGINT so that Ctrl-C
+/// still stops loops and lists that run in the shell itself.
+pub fn init_interactive_signals() {
+    for &sig in INTERACTIVE_SIGNALS {
+        unsafe { signal(sig, SigHandler::SigIgn) }.ok();
+    }
+    let action = SigAction::new(SigHandler::Handler(handle_interrupt), SaFlags::SA_RESTART, SigSet::empty());
+    unsafe { sigaction(Signal::SIGINT, &action) }.ok();
+}
+
+extern "C" fn handle_interrupt(_: libc::c_int) {
+    set_interrupted();
+    // Only async-signal-safe calls here: end the line the `^C` echo is on.
+    unsafe { libc::write(libc::STDOUT_FILENO, b"\n".as_ptr().cast(), 1) };
+}
+
+/// Restores default dispositions in a forked child before it runs a command.
+/// Ignored signals would otherwise survive `exec`. That includes SIGPIPE,
+/// which the Rust runtime ignores at startup: a program writing into a pipe
+/// whose reader has gone should die quietly, not report write errors.
+pub fn restore_defaults() {
+    for &sig in INTERACTIVE_SIGNALS.iter().chain(&[Signal::SIGPIPE]) {
+        unsafe { signal(sig, SigHandler::SigDfl) }.ok();
+    }
+}
+
+pub fn set_interrupted() {
+    INTERRUPTED.store(true, Ordering::Relaxed);
+}
+
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::Relaxed)
+}
+
+pub fn clear_interrupted() {
+    INTERRUPTED.store(false, Ordering::Relaxed);
+}
@@ -0,0 +1,66 @@
+// spell.rs
+
+use std::io::{BufRead, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+
+/// Suggests a correction for a directory path that does not exist by
+/// replacing each missing component with the closest directory name in its
+/// parent. Returns `None` if some component has no close match.
+pub fn suggest_dir(path: &str) -> Option<String> {
+    if Path::new(path).is_dir() {
+        return None;
+    }
+    let mut corrected = PathBuf::new();
+    for comp in Path::new(path).components() {
+        let name = comp.as_os_str().to_string_lossy();
+        let candidate = corrected.join(&*name);
+        if candidate.is_dir() || !matches!(comp, std::path::Component::Normal(_)) {
+            corrected = candidate;
+            continue;
+        }
+        let parent = if corrected.as_os_str().is_empty() { Path::new(".") } else { corrected.as_path() };
+        let best = std::fs::read_dir(parent).ok()?
+            .flatten()
+            .filter(|e| e.path().is_dir())
+            .filter_map(|e| e.file_name().into_string().ok())
+            .map(|entry| (levenshtein(&entry, &name), entry))
+            .filter(|(d, _)| *d <= 2 && *d < name.chars().count())
+            .min()?;
+        corrected.push(best.1);
+    }
+    Some(corrected.to_string_lossy().into_owned())
+}
+
+/// Asks the user on the terminal whether to use `suggestion` in place of
+/// `original`. Never substitutes when stdin is not a terminal.
+pub fn confirm(command: &str, original: &str, suggestion: &str) -> bool {
+    let stdin = std::io::stdin();
+    if !stdin.is_terminal() {
+        return false;
+    }
+    eprint!("{}: correct '{}' to '{}' [y/N]? ", command, original, suggestion);
+    std::io::stderr().flush().ok();
+    let mut answer = String::new();
+    if stdin.lock().read_line(&mut answer).is_err() {
+        return false;
+    }
+    matches!(answer.trim(), "y" | "Y" | "yes")
+}
+
+fn levenshtein(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut costs: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut last = i;
+        costs[0] = i + 1;
+        for (j, &cb) in b.iter().enumerate() {
+            let old = costs[j + 1];
+            costs[j + 1] = std::cmp::min(
+                std::cmp::min(costs[j] + 1, costs[j + 1] + 1),
+                last + if ca == cb { 0 } else { 1 },
+            );
+            last = old;
+        }
+    }
+    costs[b.len()]
+}
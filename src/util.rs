@@ -1,10 +1,18 @@
 // util.rs
 
-use std::io::Write;
-
 pub fn writeln_ignore_broken_pipe<W: std::io::Write, S: AsRef<str>>(mut w: W, s: S) -> std::io::Result<()> {
     match writeln!(w, "{}", s.as_ref()) {
         Err(ref e) if e.kind() == std::io::ErrorKind::BrokenPipe => Ok(()),
         other => other,
     }
-} 
\ No newline at end of file
+}
+
+/// Single-quotes `word` when it would not read back as one literal word.
+pub fn shell_quote(word: &str) -> String {
+    let plain = |c: char| c.is_ascii_alphanumeric() || "-_./=:,+@%^".contains(c);
+    if !word.is_empty() && word.chars().all(plain) {
+        word.to_string()
+    } else {
+        format!("'{}'", word.replace('\'', "'\\''"))
+    }
+}
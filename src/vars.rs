@@ -0,0 +1,233 @@
+// vars.rs
+
+use std::collections::HashMap;
+use std::env;
+use std::sync::{Mutex, MutexGuard, OnceLock};
+
+#[derive(Clone, Default)]
+struct Variable {
+    value: Option<String>,
+    exported: bool,
+    readonly: bool,
+}
+
+static VARS: OnceLock<Mutex<HashMap<String, Variable>>> = OnceLock::new();
+static SHELL_PID: OnceLock<u32> = OnceLock::new();
+/// `$0` followed by the positional parameters `$1`, `$2`, ...
+static ARGS: OnceLock<Mutex<Vec<String>>> = OnceLock::new();
+/// Variables saved to be put back later, `None` for ones that were unset.
+type Saved = Vec<(String, Option<Variable>)>;
+/// One frame per running function call, innermost last, holding the
+/// variables its `local` declarations shadowed.
+static FRAMES: Mutex<Vec<Saved>> = Mutex::new(Vec::new());
+
+fn table() -> MutexGuard<'static, HashMap<String, Variable>> {
+    VARS.get_or_init(|| {
+        let mut vars: HashMap<String, Variable> = env::vars()
+            .map(|(name, value)| (name, Variable { value: Some(value), exported: true, readonly: false }))
+            .collect();
+        // Like other shells, ignore an inherited IFS.
+        vars.insert("IFS".to_string(), Variable { value: Some(" \t\n".to_string()), ..Default::default() });
+        Mutex::new(vars)
+    })
+    .lock()
+    .unwrap()
+}
+
+/// Seeds the variable table from the environment and records the shell's
+/// pid, so that `$$` keeps naming the shell inside forked children.
+pub fn init() {
+    SHELL_PID.get_or_init(std::process::id);
+    drop(table());
+}
+
+pub fn shell_pid() -> u32 {
+    *SHELL_PID.get_or_init(std::process::id)
+}
+
+fn args() -> MutexGuard<'static, Vec<String>> {
+    ARGS.get_or_init(|| Mutex::new(vec![env::args().next().unwrap_or_default()])).lock().unwrap()
+}
+
+pub fn set_script_name(name: &str) {
+    args()[0] = name.to_string();
+}
+
+/// `$0`: the script name, or the shell's own name when interactive.
+pub fn script_name() -> String {
+    args()[0].clone()
+}
+
+pub fn positional() -> Vec<String> {
+    args()[1..].to_vec()
+}
+
+/// Replaces the positional parameters, as `set -- args` does.
+pub fn set_positional(params: Vec<String>) {
+    args().splice(1.., params);
+}
+
+pub fn is_valid_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!(chars.next(), Some(c) if c == '_' || c.is_ascii_alphabetic())
+        && chars.all(|c| c == '_' || c.is_ascii_alphanumeric())
+}
+
+pub fn get(name: &str) -> Option<String> {
+    table().get(name).and_then(|v| v.value.clone())
+}
+
+/// Assigns a shell variable. Exported variables are mirrored into the
+/// process environment so that children inherit them.
+pub fn set(name: &str, value: &str) -> Result<(), String> {
+    let mut vars = table();
+    let var = vars.entry(name.to_string()).or_default();
+    if var.readonly {
+        return Err(format!("{}: readonly variable", name));
+    }
+    var.value = Some(value.to_string());
+    if var.exported {
+        env::set_var(name, value);
+    }
+    Ok(())
+}
+
+pub fn export(name: &str) {
+    let mut vars = table();
+    let var = vars.entry(name.to_string()).or_default();
+    var.exported = true;
+    if let Some(value) = &var.value {
+        env::set_var(name, value);
+    }
+}
+
+pub fn unexport(name: &str) {
+    if let Some(var) = table().get_mut(name) {
+        var.exported = false;
+        env::remove_var(name);
+    }
+}
+
+pub fn set_readonly(name: &str) {
+    table().entry(name.to_string()).or_default().readonly = true;
+}
+
+pub fn unset(name: &str) -> Result<(), String> {
+    let mut vars = table();
+    if vars.get(name).is_some_and(|v| v.readonly) {
+        return Err(format!("{}: cannot unset: readonly variable", name));
+    }
+    vars.remove(name);
+    env::remove_var(name);
+    Ok(())
+}
+
+/// All set variables sorted by name, for a bare `set`.
+pub fn all_vars() -> Vec<(String, Option<String>)> {
+    list(|v| v.value.is_some())
+}
+
+/// Exported variables sorted by name, for `export -p`.
+pub fn exported_vars() -> Vec<(String, Option<String>)> {
+    list(|v| v.exported)
+}
+
+/// Readonly variables sorted by name, for `readonly -p`.
+pub fn readonly_vars() -> Vec<(String, Option<String>)> {
+    list(|v| v.readonly)
+}
+
+fn list(filter: impl Fn(&Variable) -> bool) -> Vec<(String, Option<String>)> {
+    let mut vars: Vec<_> = table()
+        .iter()
+        .filter(|(_, v)| filter(v))
+        .map(|(name, v)| (name.clone(), v.value.clone()))
+        .collect();
+    vars.sort();
+    vars
+}
+
+/// Applies `NAME=value` command prefixes as exported variables. Only called
+/// in a forked child, so the parent shell is unaffected.
+pub fn export_assignments(assignments: &[(String, String)]) -> Result<(), String> {
+    for (name, value) in assignments {
+        set(name, value)?;
+        export(name);
+    }
+    Ok(())
+}
+
+/// Starts the variable scope of a function call.
+pub fn push_frame() {
+    FRAMES.lock().unwrap().push(Vec::new());
+}
+
+/// Ends the innermost function scope, restoring the variables its locals
+/// shadowed.
+pub fn pop_frame() {
+    let saved = FRAMES.lock().unwrap().pop().unwrap_or_default();
+    restore(saved);
+}
+
+pub fn in_function() -> bool {
+    !FRAMES.lock().unwrap().is_empty()
+}
+
+/// Makes `name` local to the innermost function call, initially unset
+/// unless a value is given. The local keeps the export attribute of the
+/// variable it shadows.
+pub fn declare_local(name: &str, value: Option<&str>) -> Result<(), String> {
+    let mut frames = FRAMES.lock().unwrap();
+    let Some(frame) = frames.last_mut() else {
+        return Err("can only be used in a function".to_string());
+    };
+    let mut vars = table();
+    if !frame.iter().any(|(saved, _)| saved == name) {
+        let previous = vars.get(name).cloned();
+        if previous.as_ref().is_some_and(|v| v.readonly) {
+            return Err(format!("{}: readonly variable", name));
+        }
+        let exported = previous.as_ref().is_some_and(|v| v.exported);
+        frame.push((name.to_string(), previous));
+        vars.insert(name.to_string(), Variable { value: None, exported, readonly: false });
+        env::remove_var(name);
+    }
+    drop(vars);
+    match value {
+        Some(value) => set(name, value),
+        None => Ok(()),
+    }
+}
+
+/// Puts back saved variables, newest first, along with their environment
+/// entries. A variable saved as `None` did not exist and is removed.
+fn restore(saved: Saved) {
+    let mut vars = table();
+    for (name, var) in saved.into_iter().rev() {
+        match var {
+            Some(var) => {
+                match (&var.value, var.exported) {
+                    (Some(value), true) => env::set_var(&name, value),
+                    _ => env::remove_var(&name),
+                }
+                vars.insert(name, var);
+            }
+            None => {
+                vars.remove(&name);
+                env::remove_var(&name);
+            }
+        }
+    }
+}
+
+/// Runs `f` with `assignments` temporarily exported, restoring the previous
+/// variables afterwards. Used for prefix assignments on builtins.
+pub fn with_assignments<T>(assignments: &[(String, String)], f: impl FnOnce() -> T) -> Result<T, String> {
+    let saved: Saved = {
+        let vars = table();
+        assignments.iter().map(|(name, _)| (name.clone(), vars.get(name).cloned())).collect()
+    };
+    let result = export_assignments(assignments).map(|()| f());
+    restore(saved);
+    result
+}